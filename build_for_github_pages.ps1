@@ -6,7 +6,7 @@ $ScriptDir = $PSScriptRoot
 
 Write-Host "[1/4] Building dictionary data..." -ForegroundColor Yellow
 Set-Location "$ScriptDir\console"
-cargo run -- build no_query
+cargo run -- build no_query core=20000
 if ($LASTEXITCODE -ne 0) {
     Write-Host "Error: Dictionary build failed" -ForegroundColor Red
     exit 1
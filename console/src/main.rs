@@ -3,16 +3,71 @@
 
 use std::io::Write;
 use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use dictlib::compiled_dictionary::CompiledDictionary;
 use dictlib::*;
 
+// Library progress is only wanted while building, searches log a line each
+static LOG_PROGRESS : AtomicBool = AtomicBool::new(false);
+
+struct StdoutLogger;
+
+impl DebugLogger for StdoutLogger {
+    fn log(&self, logline: &str) {
+        if (LOG_PROGRESS.load(Ordering::Relaxed)) {
+            println!("{}", logline);
+        }
+    }
+
+    fn log_error(&self, logline: &str) {
+        eprintln!("{}", logline);
+    }
+}
+
 fn main() {
     let args : Vec<String> = std::env::args().collect();
 
+    // stats <file> [json]
+    if let Some(i) = args.iter().position(|x| x.eq_ignore_ascii_case("stats")) {
+        let path = args.get(i + 1).expect("stats needs a dictionary file");
+        let json = args.iter().any(|x| x.eq_ignore_ascii_case("json"));
+
+        let buffer = std::fs::read(path).unwrap();
+        let dict = CompiledDictionary::deserialize(&mut data_reader::DataReader::new(&buffer[..]));
+        let stats = dict.stats();
+        if (stats.total_bytes != buffer.len()) {
+            // Sections are measured by writing the loaded dictionary again
+            eprintln!("Warning: {} is {} bytes but writes back as {}, section sizes are approximate", path, buffer.len(), stats.total_bytes);
+        }
+
+        if (json) {
+            println!("{}", stats.to_json());
+        }
+        else {
+            println!("{}", path);
+            println!("{}", stats);
+        }
+
+        return;
+    }
+
+    // Library errors, and progress messages while building
+    set_debug_logger(Box::new(StdoutLogger));
+
     let build = args.iter().any(|x| x.eq_ignore_ascii_case("build"));
+    let verify = args.iter().any(|x| x.eq_ignore_ascii_case("verify"));
     let test_set = args.iter().any(|x| x.eq_ignore_ascii_case("test_set"));
     let no_query = args.iter().any(|x| x.eq_ignore_ascii_case("no_query"));
+    let ranking_config_path = args.iter().find_map(|x| x.strip_prefix("ranking="));
+    let eval = args.iter().any(|x| x.eq_ignore_ascii_case("eval"));
+    let save_baseline = args.iter().any(|x| x.eq_ignore_ascii_case("save_baseline"));
+    let tune = args.iter().any(|x| x.eq_ignore_ascii_case("tune"));
+    let weights_path = args.iter().find_map(|x| x.strip_prefix("weights="));
+    // Any number of custom=<path> user dictionaries, see Builder::parse_custom
+    let custom_paths : Vec<String> = args.iter().filter_map(|x| x.strip_prefix("custom=")).map(|x| x.to_owned()).collect();
+    // Also split the dictionary into a core of the first n entries and a tail, for the web client to load progressively
+    let core_entries : Option<usize> = args.iter().find_map(|x| x.strip_prefix("core=")).map(|x| x.parse().unwrap());
 
     let (data_path, name, print_debug) = if test_set {
         ("../test", "test", true)
@@ -23,29 +78,106 @@ fn main() {
 
 
     let index_path = format!("{}/{}.jyp_dict", data_path, name);
+    // Example sentences are written separately so the main dictionary download doesn't grow
+    let examples_path = format!("{}/{}.jyp_examples", data_path, name);
+    let core_path = format!("{}/{}.jyp_core", data_path, name);
+    let tail_path = format!("{}/{}.jyp_tail", data_path, name);
+    let golden_path = args.iter().find_map(|x| x.strip_prefix("golden=")).map(|x| x.to_owned())
+        .unwrap_or_else(|| format!("{}/golden_queries.tsv", data_path));
+    let k = args.iter().find_map(|x| x.strip_prefix("k=")).map(|x| x.parse().unwrap()).unwrap_or(5);
 
-    if (build)
+    // Tuned weights cover both the build heuristics and search ranking
+    let weights = if let Some(path) = weights_path {
+        println!("Reading weights from {}", path);
+        dictlib::tune::TunedWeights::parse(path)
+    }
+    else {
+        dictlib::tune::TunedWeights {
+            ranking: Default::default(),
+            heuristics: dictlib::heuristics::HeuristicsConfig::from_data_dir(data_path),
+        }
+    };
+
+    if (tune)
     {
-        println!("Building...");
-        let mut builder = dictlib::builder::Builder::default();
-        let trad_to_frequency = dictlib::builder::TraditionalToFrequencies::parse(&format!("{}/frequencies.txt", data_path));
+        // tune [golden=<path>] [k=<n>] [rounds=<n>] [out=<path>] [weights=<starting weights>]
+        let rounds = args.iter().find_map(|x| x.strip_prefix("rounds=")).map(|x| x.parse().unwrap()).unwrap_or(5);
+        let out_path = args.iter().find_map(|x| x.strip_prefix("out=")).map(|x| x.to_owned())
+            .unwrap_or_else(|| format!("{}/tuned_weights.json", data_path));
+
+        let mut raw = dictlib::builder::Builder::parse_data_dir(data_path);
+        for path in &custom_paths {
+            raw.parse_custom(path, &dictlib::builder::custom_source_name(path));
+        }
+
+        let queries = dictlib::eval::GoldenQuery::parse(&golden_path);
+        println!("Tuning on {} queries from {}", queries.len(), golden_path);
+
+        let mut tuner = dictlib::tune::Tuner::new(&raw.entries, &queries, k);
+        let result = tuner.tune(weights, rounds);
 
-        // Cedict is
-        // Traditional / Pinyin / English Definition.
-        builder.parse_cedict(&format!("{}/cedict_ts.u8", data_path), &trad_to_frequency);
+        for step in &result.steps {
+            println!("{} {} -> {} (MRR {:.4})", step.weight, step.old_value, step.new_value, step.mrr);
+        }
+
+        println!("MRR {:.4} -> {:.4} after {} evaluations", result.initial_mrr, result.mrr, result.evaluations);
+        println!("Writing tuned weights to {}", out_path);
+        std::fs::write(&out_path, result.weights.to_json()).unwrap();
+        return;
+    }
 
-        let trad_to_jyutping = dictlib::builder::TraditionalToJyutping::parse(&format!("{}/cccedict-canto-readings-150923.txt", data_path));
-        builder.annotate(&trad_to_jyutping);
+    if (verify)
+    {
+        // verify [test_set] [custom=<path>] [weights=<path>] [core=<n>]
+        // Rebuilds from the inputs and checks the files on disk match byte for byte
+        println!("Rebuilding...");
+        LOG_PROGRESS.store(true, Ordering::Relaxed);
+        let builder = dictlib::builder::Builder::from_data_dir_with_custom(data_path, &custom_paths, &weights.heuristics);
+        let mut rebuilt = CompiledDictionary::from_builder(builder);
+        let examples = rebuilt.examples.take();
 
-        builder.parse_ccanto(&format!("{}/cccanto-webdist.txt", data_path));
+        let mut ok = verify_file(&index_path, |w| { rebuilt.serialize(w)?; Ok(()) });
+
+        let existing = std::fs::read(&index_path).unwrap_or_default();
+        match CompiledDictionary::read_input_hash(&existing) {
+            Some(hash) => println!("Input hash {:016x} on disk, {:016x} rebuilt", hash, rebuilt.input_hash),
+            None => println!("No readable input hash on disk, {:016x} rebuilt", rebuilt.input_hash),
+        }
+
+        if let Some(core_entries) = core_entries {
+            let core_entries = core_entries.min(rebuilt.entries.len());
+            ok &= verify_file(&core_path, |w| { rebuilt.serialize_core(w, core_entries)?; Ok(()) });
+            ok &= verify_file(&tail_path, |w| { rebuilt.serialize_chunk(w, core_entries..rebuilt.entries.len())?; Ok(()) });
+        }
+
+        if let Some(examples) = examples {
+            ok &= verify_file(&examples_path, |w| {
+                examples.serialize(w)?;
+                w.write_u64(0)
+            });
+        }
+
+        if (!ok) {
+            std::process::exit(1);
+        }
+
+        println!("Build is reproducible");
+        return;
+    }
+
+    if (build)
+    {
+        println!("Building...");
+        LOG_PROGRESS.store(true, Ordering::Relaxed);
+        let heuristics = &weights.heuristics;
+        let builder = dictlib::builder::Builder::from_data_dir_with_custom(data_path, &custom_paths, heuristics);
 
         if print_debug {
             println!("Data\n{:#?}", builder);
         }
 
-        builder.apply_additional_heuristics();
-
-        let built_dictionary = CompiledDictionary::from_builder(builder);
+        let mut built_dictionary = CompiledDictionary::from_builder(builder);
+        let examples = built_dictionary.examples.take();
 
         let dump_entries = false;
         if (dump_entries)
@@ -55,8 +187,45 @@ fn main() {
 
         println!("Writing to {}", &index_path);
         let mut data_writer = data_writer::DataWriter::new(&index_path);
-        built_dictionary.serialize(&mut data_writer).unwrap();
+        let stats = built_dictionary.serialize(&mut data_writer).unwrap();
+        println!("{}", stats);
+
+        if let Some(core_entries) = core_entries {
+            let core_entries = core_entries.min(built_dictionary.entries.len());
+
+            println!("Writing first {} entries to {}", core_entries, core_path);
+            let mut core_writer = data_writer::DataWriter::new(&core_path);
+            let core_stats = built_dictionary.serialize_core(&mut core_writer, core_entries).unwrap();
+            println!("Core bytes = {}", core_stats.total_bytes);
+
+            println!("Writing remaining entries to {}", tail_path);
+            let mut tail_writer = data_writer::DataWriter::new(&tail_path);
+            let tail_stats = built_dictionary.serialize_chunk(&mut tail_writer, core_entries..built_dictionary.entries.len()).unwrap();
+            println!("Tail bytes = {}", tail_stats.total_bytes);
+        }
+        else {
+            for path in [&core_path, &tail_path] {
+                if std::path::Path::new(path).exists() {
+                    // Stale chunks would belong to a different build
+                    std::fs::remove_file(path).unwrap();
+                }
+            }
+        }
+
+        if let Some(examples) = examples {
+            println!("Writing examples to {}", examples_path);
+            let mut examples_writer = data_writer::DataWriter::new(&examples_path);
+            examples.serialize(&mut examples_writer).unwrap();
+            // End padding
+            examples_writer.write_u64(0).unwrap();
+        }
+        else if std::path::Path::new(&examples_path).exists() {
+            // Stale examples would belong to a different build
+            std::fs::remove_file(&examples_path).unwrap();
+        }
+
         println!("Writing done!");
+        LOG_PROGRESS.store(false, Ordering::Relaxed);
         
     }
 
@@ -67,13 +236,61 @@ fn main() {
         f.read_to_end(&mut buffer).unwrap();
 
         let mut data_reader = data_reader::DataReader::new(&buffer[..]);
-        CompiledDictionary::deserialize(&mut data_reader)
+        let mut dict = CompiledDictionary::deserialize(&mut data_reader);
+
+        if std::path::Path::new(&examples_path).exists() {
+            println!("Reading examples from {}", examples_path);
+            let buffer = std::fs::read(&examples_path).unwrap();
+            let mut data_reader = data_reader::DataReader::new(&buffer[..]);
+            let examples = dictlib::examples::ExampleStore::deserialize(&mut data_reader);
+            if (dict.examples_fit(&examples)) {
+                dict.set_examples(examples);
+            }
+            else {
+                println!("Skipping examples from {}, built for a different dictionary", examples_path);
+            }
+        }
+
+        dict
     };
 
     if (print_debug) {
         println!("Compiled Dictionary\n{:#?}", compiled_dictionary);
     }
 
+    let ranking_config = if let Some(path) = ranking_config_path {
+        println!("Reading ranking config from {}", path);
+        dictlib::search::RankingConfig::parse(path)
+    }
+    else {
+        weights.ranking.clone()
+    };
+
+    if (eval)
+    {
+        // eval [golden=<path>] [baseline=<path>] [k=<n>] [save_baseline]
+        let baseline_path = args.iter().find_map(|x| x.strip_prefix("baseline=")).map(|x| x.to_owned())
+            .unwrap_or_else(|| format!("{}/golden_baseline.json", data_path));
+
+        let queries = dictlib::eval::GoldenQuery::parse(&golden_path);
+        println!("Evaluating {} queries from {}", queries.len(), golden_path);
+        let report = dictlib::eval::evaluate(&compiled_dictionary, &queries, k, &ranking_config);
+        println!("{}", report);
+
+        if std::path::Path::new(&baseline_path).exists() {
+            let baseline = dictlib::eval::EvalReport::parse(&baseline_path);
+            println!("Compared to baseline {}", baseline_path);
+            println!("{}", report.diff(&baseline));
+        }
+
+        if (save_baseline) {
+            println!("Saving baseline to {}", baseline_path);
+            report.save(&baseline_path);
+        }
+
+        return;
+    }
+
     let mut buffer = String::new();
 
     if (no_query)
@@ -91,13 +308,65 @@ fn main() {
         std::io::stdin().read_line(&mut buffer).unwrap();
         println!("\n\n");
 
+        let query = buffer.trim();
+        let mut query_chars = query.chars();
+        if let (Some(c), None) = (query_chars.next(), query_chars.next()) {
+            if let Some(info) = compiled_dictionary.get_character_info(c) {
+                println!("(Character {:?} radical {:?})\n{:#?}", c, dictlib::character_info::radical_char(info.radical), info);
+            }
+        }
+
+        // Wildcard queries like 食* list words containing the characters
+        if let Some(words_query) = dictlib::words_containing::WordsContainingQuery::parse_pattern(query) {
+            let result = compiled_dictionary.words_containing(&words_query, 0, 20);
+            println!("{} words", result.total);
+            for m in result.matches {
+                let display = compiled_dictionary.get_diplay_entry(m.match_obj.entry_id);
+                println!("{} {} {:?}", display.characters, display.jyutping, display.english_definitions);
+            }
+
+            continue;
+        }
+
         let stopwatch = Box::new(NativeStopwatch::new());
-        let result = compiled_dictionary.search(&buffer.trim(), 5, stopwatch);
+        let result = compiled_dictionary.search_with_config(&buffer.trim(), 5, &ranking_config, stopwatch);
 
         for m in result.matches
         {
             let display = compiled_dictionary.get_diplay_entry(m.match_obj.entry_id);
             println!("(Match {:?})\n{:#?}", m, display);
+
+            for example in compiled_dictionary.examples_for(m.match_obj.entry_id, 3) {
+                println!("  {} ({}) {}", example.chinese, example.jyutping, example.english);
+            }
         }
     }
 }
+
+/// Compares what write produces against the file at path, printing where they first differ
+fn verify_file(path: &str, write: impl FnOnce(&mut data_writer::DataWriter<Vec<u8>>) -> std::io::Result<()>) -> bool {
+    let mut writer = data_writer::DataWriter {
+        write_len: 0,
+        inner: std::io::BufWriter::new(Vec::new()),
+    };
+    write(&mut writer).unwrap();
+    let rebuilt = writer.inner.into_inner().unwrap();
+
+    let existing = match std::fs::read(path) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("{}: could not read ({})", path, e);
+            return false;
+        }
+    };
+
+    if (existing == rebuilt) {
+        println!("{}: {} bytes match", path, existing.len());
+        return true;
+    }
+
+    let first_difference = existing.iter().zip(&rebuilt).position(|(a, b)| a != b)
+        .unwrap_or(existing.len().min(rebuilt.len()));
+    println!("{}: differs at byte {} ({} bytes on disk, {} rebuilt)", path, first_difference, existing.len(), rebuilt.len());
+    false
+}
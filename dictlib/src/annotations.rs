@@ -0,0 +1,402 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::builder::DictionaryEntry;
+use crate::data_reader::DataReader;
+use crate::data_writer::DataWriter;
+use crate::StringVecSet;
+
+// A leading "(label)" on a definition is pulled out when it is one of these,
+// anything else in brackets such as "(of a person)" stays part of the text
+pub const USAGE_LABELS: &[&str] = &[
+    "Cantonese", "Mandarin", "dialect", "slang", "spoken", "colloquial", "written", "literary",
+    "formal", "informal", "polite", "honorific", "humble", "derogatory", "vulgar", "offensive",
+    "euphemism", "old", "archaic", "obsolete", "idiom", "proverb", "phrase", "loanword",
+    "onomatopoeia", "noun", "verb", "adjective", "adverb",
+    "Buddhism", "Taoism", "Christianity", "Taiwan", "Hong Kong", "Macau",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[cfg_attr(feature = "tsify", derive(tsify::Tsify))]
+#[repr(u8)]
+pub enum CrossReferenceKind {
+    VariantOf,
+    OldVariantOf,
+    SeeAlso,
+    See,
+    AbbreviationFor,
+    SameAs,
+    UsedIn,
+}
+
+// Longer prefixes first so "old variant of" is not read as "variant of"
+const CROSS_REFERENCE_PREFIXES: &[(&str, CrossReferenceKind)] = &[
+    ("old variant of ", CrossReferenceKind::OldVariantOf),
+    ("variant of ", CrossReferenceKind::VariantOf),
+    ("see also ", CrossReferenceKind::SeeAlso),
+    ("see ", CrossReferenceKind::See),
+    ("abbr. for ", CrossReferenceKind::AbbreviationFor),
+    ("abbr. of ", CrossReferenceKind::AbbreviationFor),
+    ("same as ", CrossReferenceKind::SameAs),
+    ("used in ", CrossReferenceKind::UsedIn),
+];
+
+impl CrossReferenceKind {
+    pub fn from_u8(x: u8) -> Self {
+        match x {
+            0 => CrossReferenceKind::VariantOf,
+            1 => CrossReferenceKind::OldVariantOf,
+            2 => CrossReferenceKind::SeeAlso,
+            3 => CrossReferenceKind::See,
+            4 => CrossReferenceKind::AbbreviationFor,
+            5 => CrossReferenceKind::SameAs,
+            6 => CrossReferenceKind::UsedIn,
+            _ => panic!("Unknown cross reference kind {}", x),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[cfg_attr(feature = "tsify", derive(tsify::Tsify))]
+pub struct Classifier {
+    pub characters: String,
+    // Empty if the classifier has no entry of its own
+    pub jyutping: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "tsify", derive(tsify::Tsify))]
+pub struct CrossReference {
+    pub kind: CrossReferenceKind,
+    pub characters: String,
+    pub jyutping: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "tsify", derive(tsify::Tsify))]
+pub struct DefinitionLabel {
+    // Index into the entry's definitions
+    pub definition: u8,
+    pub label: String,
+}
+
+/// Structured data pulled out of the free text definitions.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[cfg_attr(feature = "tsify", derive(tsify::Tsify))]
+pub struct EntryAnnotations {
+    pub classifiers: Vec<Classifier>,
+    pub labels: Vec<DefinitionLabel>,
+    pub cross_references: Vec<CrossReference>,
+    // Trailing "# comment" from the source line
+    pub comments: Vec<String>,
+}
+
+impl EntryAnnotations {
+    pub fn is_empty(&self) -> bool {
+        self.classifiers.is_empty() && self.labels.is_empty() && self.cross_references.is_empty() && self.comments.is_empty()
+    }
+
+    pub fn add_comment(&mut self, comment: &str) {
+        if (!comment.is_empty() && !self.comments.iter().any(|x| x == comment)) {
+            self.comments.push(comment.to_owned());
+        }
+    }
+
+    pub fn labels_for(&self, definition: usize) -> impl Iterator<Item = &str> {
+        self.labels.iter().filter(move |x| x.definition as usize == definition).map(|x| x.label.as_str())
+    }
+
+    pub fn write<T : std::io::Write>(&self, writer: &mut DataWriter<T>) -> std::io::Result<()> {
+        writer.write_u8(self.classifiers.len() as u8)?;
+        for c in &self.classifiers {
+            writer.write_string(&c.characters)?;
+            writer.write_string(&c.jyutping)?;
+        }
+
+        writer.write_u8(self.labels.len() as u8)?;
+        for l in &self.labels {
+            writer.write_u8(l.definition)?;
+            writer.write_string(&l.label)?;
+        }
+
+        writer.write_u8(self.cross_references.len() as u8)?;
+        for x in &self.cross_references {
+            writer.write_u8(x.kind as u8)?;
+            writer.write_string(&x.characters)?;
+            writer.write_string(&x.jyutping)?;
+        }
+
+        writer.write_u8(self.comments.len() as u8)?;
+        for c in &self.comments {
+            writer.write_string(c)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read(reader: &mut DataReader) -> Self {
+        let mut annotations = Self::default();
+
+        let classifier_count = reader.read_u8();
+        for _ in 0..classifier_count {
+            annotations.classifiers.push(Classifier {
+                characters: reader.read_string().to_owned(),
+                jyutping: reader.read_string().to_owned(),
+            });
+        }
+
+        let label_count = reader.read_u8();
+        for _ in 0..label_count {
+            annotations.labels.push(DefinitionLabel {
+                definition: reader.read_u8(),
+                label: reader.read_string().to_owned(),
+            });
+        }
+
+        let cross_reference_count = reader.read_u8();
+        for _ in 0..cross_reference_count {
+            annotations.cross_references.push(CrossReference {
+                kind: CrossReferenceKind::from_u8(reader.read_u8()),
+                characters: reader.read_string().to_owned(),
+                jyutping: reader.read_string().to_owned(),
+            });
+        }
+
+        let comment_count = reader.read_u8();
+        for _ in 0..comment_count {
+            annotations.comments.push(reader.read_string().to_owned());
+        }
+
+        annotations
+    }
+}
+
+/// Move classifiers, usage labels and cross references out of the definition text.
+/// Runs after heuristics, which still match on the raw text, and after merging so
+/// label definition indexes are final.
+pub fn extract_annotations(entries: &mut [DictionaryEntry]) {
+    // Readings for linked characters come from the cheapest entry with that spelling
+    let mut trad_to_jyutping: BTreeMap<String, (u32, String)> = BTreeMap::new();
+    for e in entries.iter() {
+        if (e.jyutping.is_empty()) {
+            continue;
+        }
+
+        let existing = trad_to_jyutping.entry(e.traditional.clone()).or_insert((u32::MAX, String::new()));
+        if (e.cost < existing.0) {
+            *existing = (e.cost, e.jyutping.clone());
+        }
+    }
+
+    let lookup = |characters: &str| -> String {
+        trad_to_jyutping.get(characters).map(|(_, j)| j.clone()).unwrap_or_default()
+    };
+
+    for e in entries.iter_mut() {
+        let mut definition_offset = 0;
+
+        for group in &mut e.groups {
+            let mut definitions = StringVecSet::default();
+
+            for def in group.english_sets.inner.drain(..) {
+                if let Some(characters) = parse_classifiers(&def) {
+                    for c in characters {
+                        if (!e.annotations.classifiers.iter().any(|x| x.characters == c)) {
+                            e.annotations.classifiers.push(Classifier {
+                                jyutping: lookup(&c),
+                                characters: c,
+                            });
+                        }
+                    }
+
+                    continue;
+                }
+
+                let (labels, text) = split_labels(&def);
+                let text = if let Some((kind, characters, rest)) = split_cross_reference(text) {
+                    e.annotations.cross_references.push(CrossReference {
+                        kind,
+                        jyutping: lookup(&characters),
+                        characters,
+                    });
+
+                    rest
+                }
+                else {
+                    text
+                };
+
+                // Nothing left but the reference
+                if (text.is_empty()) {
+                    continue;
+                }
+
+                definitions.add_clone(text);
+                let index = definition_offset + definitions.inner.iter().position(|x| x == text).unwrap();
+                for label in labels {
+                    let label = DefinitionLabel {
+                        definition: index as u8,
+                        label: label.to_owned(),
+                    };
+
+                    if (!e.annotations.labels.contains(&label)) {
+                        e.annotations.labels.push(label);
+                    }
+                }
+            }
+
+            definition_offset += definitions.inner.len();
+            group.english_sets = definitions;
+        }
+    }
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c, '\u{3007}' | '\u{3400}'..='\u{9FFF}' | '\u{F900}'..='\u{FAFF}' | '\u{20000}'..='\u{2FFFF}')
+}
+
+/// Reads a linked word in either source's format, returning the traditional characters and the remaining text
+///     CC-CEDICT  一併|一并[yi1 bing4]
+///     CC-Canto   同tóng [同]
+fn parse_reference(s: &str) -> Option<(String, &str)> {
+    let s = s.trim_start();
+    let characters_len: usize = s.chars().take_while(|c| is_cjk(*c)).map(|c| c.len_utf8()).sum();
+    if (characters_len == 0) {
+        return None;
+    }
+
+    let characters = s[..characters_len].to_owned();
+    let mut rest = &s[characters_len..];
+
+    if let Some(simplified) = rest.strip_prefix('|') {
+        let simplified_len: usize = simplified.chars().take_while(|c| is_cjk(*c)).map(|c| c.len_utf8()).sum();
+        rest = &simplified[simplified_len..];
+    }
+
+    // Tone marked pinyin directly after the characters
+    let pinyin_len: usize = rest.chars().take_while(|c| c.is_alphabetic() && !is_cjk(*c)).map(|c| c.len_utf8()).sum();
+    rest = &rest[pinyin_len..];
+
+    let trimmed = rest.trim_start();
+    if (trimmed.starts_with('[')) {
+        if let Some(end) = trimmed.find(']') {
+            rest = &trimmed[end + 1..];
+        }
+    }
+
+    Some((characters, rest))
+}
+
+/// "CL:隻|只[zhi1],頭|头[tou2]" or "M: 件jiàn [件]"
+fn parse_classifiers(def: &str) -> Option<Vec<String>> {
+    let mut rest = def.strip_prefix("CL:").or_else(|| def.strip_prefix("M:"))?;
+
+    let mut classifiers = Vec::new();
+    while let Some((characters, r)) = parse_reference(rest) {
+        classifiers.push(characters);
+        rest = r.trim_start_matches([',', ';', ' ']);
+    }
+
+    if (classifiers.is_empty()) {
+        return None;
+    }
+
+    Some(classifiers)
+}
+
+fn split_labels(def: &str) -> (Vec<&str>, &str) {
+    let mut labels = Vec::new();
+    let mut rest = def;
+
+    while let Some(inner) = rest.strip_prefix('(') {
+        let Some(end) = inner.find(')') else {
+            break;
+        };
+
+        let label = &inner[..end];
+        let remaining = inner[end + 1..].trim_start();
+        if (!USAGE_LABELS.contains(&label) || remaining.is_empty()) {
+            break;
+        }
+
+        labels.push(label);
+        rest = remaining;
+    }
+
+    (labels, rest)
+}
+
+fn split_cross_reference(def: &str) -> Option<(CrossReferenceKind, String, &str)> {
+    for (prefix, kind) in CROSS_REFERENCE_PREFIXES {
+        if let Some(rest) = def.strip_prefix(prefix) {
+            let (characters, rest) = parse_reference(rest)?;
+            return Some((*kind, characters, rest.trim_start_matches([',', ';', ' ']).trim_end()));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EntrySource;
+
+    fn entry(traditional: &str, jyutping: &str, definitions: &[&str], source: EntrySource) -> DictionaryEntry {
+        let mut english_sets = StringVecSet::default();
+        for d in definitions {
+            english_sets.add_clone(d);
+        }
+
+        DictionaryEntry::new(traditional, jyutping, source, english_sets, 0)
+    }
+
+    #[test]
+    fn references() {
+        assert_eq!(Some(("一併".to_owned(), ", to lump together")), parse_reference("一併|一并, to lump together"));
+        assert_eq!(Some(("隻".to_owned(), ",頭|头[tou2]")), parse_reference("隻|只[zhi1],頭|头[tou2]"));
+        assert_eq!(Some(("同".to_owned(), " 3. together")), parse_reference("同tóng [同] 3. together"));
+        assert_eq!(None, parse_reference("Taiwan"));
+
+        assert_eq!(Some(vec!["隻".to_owned(), "頭".to_owned()]), parse_classifiers("CL:隻|只[zhi1],頭|头[tou2]"));
+        assert_eq!(Some(vec!["件".to_owned()]), parse_classifiers("M: 件jiàn [件] "));
+        assert_eq!(None, parse_classifiers("tiger"));
+
+        assert_eq!((vec!["slang", "Cantonese"], "wife"), split_labels("(slang) (Cantonese) wife"));
+        assert_eq!((vec![], "(of a person) greedy"), split_labels("(of a person) greedy"));
+        assert_eq!((vec![], "(slang)"), split_labels("(slang)"));
+
+        assert_eq!(None, split_cross_reference("abbr. for Taiwan"));
+        assert_eq!(Some((CrossReferenceKind::OldVariantOf, "分".to_owned(), "")), split_cross_reference("old variant of 分fèn [分]"));
+    }
+
+    #[test]
+    fn extract() {
+        let mut entries = vec![
+            entry("虎", "fu2", &["tiger", "CL:隻|只[zhi1]"], EntrySource::CEDict),
+            entry("隻", "zek3", &["classifier for animals"], EntrySource::CEDict),
+            entry("一並", "jat1 bing6", &["variant of 一併|一并, to lump together", "see also 一併"], EntrySource::CEDict),
+        ];
+
+        entries[0].groups.push(crate::builder::DefinitionGroup {
+            source: EntrySource::CCanto,
+            english_sets: StringVecSet::single("(slang) wife".to_owned()),
+        });
+
+        extract_annotations(&mut entries);
+
+        let tiger = &entries[0];
+        assert_eq!(vec!["tiger", "wife"], tiger.definitions().collect::<Vec<_>>());
+        assert_eq!(vec![Classifier { characters: "隻".to_owned(), jyutping: "zek3".to_owned() }], tiger.annotations.classifiers);
+        assert_eq!(vec!["slang"], tiger.annotations.labels_for(1).collect::<Vec<_>>());
+        assert_eq!(0, tiger.annotations.labels_for(0).count());
+
+        let together = &entries[2];
+        assert_eq!(vec!["to lump together"], together.definitions().collect::<Vec<_>>());
+        assert_eq!(2, together.annotations.cross_references.len());
+        assert_eq!(CrossReferenceKind::VariantOf, together.annotations.cross_references[0].kind);
+        assert_eq!(CrossReferenceKind::SeeAlso, together.annotations.cross_references[1].kind);
+        assert_eq!("", together.annotations.cross_references[1].jyutping);
+    }
+}
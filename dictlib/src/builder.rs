@@ -1,22 +1,148 @@
 use std::collections::BTreeMap;
-use crate::{EntrySource, JyutpingSplitter, StringVecSet};
+use serde::Deserialize;
+use crate::{EntrySource, StringVecSet};
+use crate::heuristics::HeuristicsConfig;
+use crate::annotations::{extract_annotations, EntryAnnotations};
+use crate::examples::{link_examples, ExampleSentence};
+use crate::character_info::CharacterInfoTable;
+use crate::input_hash::InputHasher;
 
 #[derive(Debug, Default)]
 pub struct Builder
 {
     pub trad_to_frequency : TraditionalToFrequencies,
     pub entries: Vec<DictionaryEntry>,
+
+    // Optional example sentence corpus, linked to entries by link_examples
+    pub examples: Vec<ExampleSentence>,
+
+    // Optional radicals, stroke counts and readings for individual characters
+    pub character_info: CharacterInfoTable,
+
+    // Identifies the files and settings the build read, see hash_inputs. Zero when built from code.
+    pub input_hash: u64,
 }
 
+// Files parse_data_dir reads from a data directory, the optional ones may be missing
+pub const DATA_DIR_INPUTS: &[&str] = &[
+    "frequencies.txt",
+    "cedict_ts.u8",
+    "cccedict-canto-readings-150923.txt",
+    "cccanto-webdist.txt",
+    "canto_word_frequencies.txt",
+    "examples.tsv",
+    "unihan.txt",
+];
+
 pub const MAX_STATIC_COST_F : f32 = 7_000.0;
 pub const MAX_STATIC_COST   : u32 = 7_000;
 
+// Custom entries without an explicit cost, around a common two character CC-CEDICT word
+pub const DEFAULT_CUSTOM_COST : u32 = 3_000;
+
 impl Builder {
-    pub fn parse_ccanto(&mut self, path : &str)
+    /// Run the full build over a data directory laid out like `full/` or `test/`
+    pub fn from_data_dir(data_path: &str, heuristics: &HeuristicsConfig) -> Self
+    {
+        Self::from_data_dir_with_custom(data_path, &[], heuristics)
+    }
+
+    /// As from_data_dir with extra custom dictionary files, see parse_custom
+    pub fn from_data_dir_with_custom(data_path: &str, custom_paths: &[String], heuristics: &HeuristicsConfig) -> Self
+    {
+        let mut builder = Self::parse_data_dir(data_path);
+        for path in custom_paths {
+            builder.parse_custom(path, &custom_source_name(path));
+        }
+
+        builder.finish(heuristics);
+        builder.input_hash = hash_inputs(data_path, custom_paths, heuristics);
+        builder
+    }
+
+    /// The build steps after parsing, shared with the tuner so its dictionaries match real builds
+    pub fn finish(&mut self, heuristics: &HeuristicsConfig)
+    {
+        self.apply_additional_heuristics(heuristics);
+        self.merge_duplicates();
+        self.extract_annotations();
+        self.link_examples();
+    }
+
+    /// Parse all sources from a data directory without applying any heuristics
+    pub fn parse_data_dir(data_path: &str) -> Self
+    {
+        let mut builder = Self::default();
+        let mut trad_to_frequency = TraditionalToFrequencies::parse(&format!("{}/frequencies.txt", data_path));
+
+        // Optional, Cantonese word frequencies override the Mandarin character based costs
+        let word_frequencies_path = format!("{}/canto_word_frequencies.txt", data_path);
+        let word_frequencies = if std::path::Path::new(&word_frequencies_path).exists() {
+            Some(WordFrequencies::parse(&word_frequencies_path))
+        }
+        else {
+            None
+        };
+
+        // Cantonese characters missing from the Mandarin list fall back to their frequency in the words
+        if let Some(word_frequencies) = &word_frequencies {
+            trad_to_frequency.add_canto(word_frequencies);
+        }
+
+        // Cedict is
+        // Traditional / Pinyin / English Definition.
+        builder.parse_cedict(&format!("{}/cedict_ts.u8", data_path), &trad_to_frequency);
+
+        let trad_to_jyutping = TraditionalToJyutping::parse(&format!("{}/cccedict-canto-readings-150923.txt", data_path));
+        builder.annotate(&trad_to_jyutping);
+
+        builder.parse_ccanto(&format!("{}/cccanto-webdist.txt", data_path), &trad_to_frequency);
+
+        if let Some(word_frequencies) = &word_frequencies {
+            builder.apply_word_frequencies(word_frequencies);
+        }
+
+        // Optional, example sentences are compiled into a separate section
+        let examples_path = format!("{}/examples.tsv", data_path);
+        if std::path::Path::new(&examples_path).exists() {
+            builder.examples = ExampleSentence::parse(&examples_path);
+        }
+
+        // Optional, Unihan style character metadata
+        let unihan_path = format!("{}/unihan.txt", data_path);
+        if std::path::Path::new(&unihan_path).exists() {
+            builder.character_info = CharacterInfoTable::parse(&unihan_path);
+        }
+
+        builder
+    }
+
+    pub fn apply_word_frequencies(&mut self, word_frequencies: &WordFrequencies)
+    {
+        let mut matched = 0;
+        for e in &mut self.entries {
+            if let Some(x) = word_frequencies.get(&e.traditional) {
+                e.cost = x.cost;
+                e.word_frequency = Some(x.count);
+                matched += 1;
+            }
+        }
+
+        debug_log!("Applied word frequencies to {} of {} entries", matched, self.entries.len());
+    }
+
+    pub fn parse_ccanto(&mut self, path : &str, trad_to_frequency : &TraditionalToFrequencies)
     {
         let size_at_start = self.entries.len();
 
         let data = std::fs::read_to_string(path).unwrap();
+        self.parse_ccanto_str(&data, trad_to_frequency);
+
+        debug_log!("Read {} dictionary entries from {}", {self.entries.len() - size_at_start}, path);
+    }
+
+    pub fn parse_ccanto_str(&mut self, data : &str, trad_to_frequency : &TraditionalToFrequencies)
+    {
         for line in data.lines()
         {
             if (line.len() == 0) {
@@ -46,8 +172,10 @@ impl Builder {
 
             let mut english = &rest[jyutping_end+2..];
 
+            let mut comment = "";
             if let Some(end_comment) = english.find('#')
             {
+                comment = english[end_comment + 1..].trim();
                 english = &english[0..end_comment];
             }
 
@@ -62,25 +190,16 @@ impl Builder {
                 definitions.add_clone(def);
             }
 
-            //trad_to_frequency.add_canto(&traditional);
-            let mut jyutping_count = 0;
-            for _ in JyutpingSplitter::new(jyutping) {
-                jyutping_count += 1;
+            // Replaced by the word frequency when there is one, otherwise the heuristics add a base cost
+            let mut cost = 0;
+            for c in traditional.chars() {
+                cost += trad_to_frequency.get_or_default(c).cost;
             }
 
-            let mut cost = (15_000 + jyutping_count * 1_000) as u32;
-            cost += cost_heuristic(&definitions.inner);
-
-            self.entries.push(DictionaryEntry {
-                traditional: traditional.to_owned(),
-                jyutping: jyutping.to_owned(),
-                english_sets: definitions,
-                source: EntrySource::CCanto,
-                cost,
-            });
+            let mut entry = DictionaryEntry::new(traditional, jyutping, EntrySource::CCanto, definitions, cost);
+            entry.annotations.add_comment(comment);
+            self.entries.push(entry);
         }
-
-        println!("Read {} dictionary entries from {}", {self.entries.len() - size_at_start}, path);
     }
 
     pub fn annotate(&mut self, trad_to_jyutping: &TraditionalToJyutping) {
@@ -118,8 +237,10 @@ impl Builder {
 
             let mut english = &rest[pinyin_end+2..];
 
+            let mut comment = "";
             if let Some(end_comment) = english.find('#')
             {
+                comment = english[end_comment + 1..].trim();
                 english = &english[0..end_comment];
             }
 
@@ -139,93 +260,297 @@ impl Builder {
                 cost += trad_to_frequency.get_or_default(c).cost;
             }
 
-            cost += cost_heuristic(&definitions.inner);
-
             //println!("{} - {:?}", traditional, definitions);
-            self.entries.push(DictionaryEntry {
-                traditional: traditional.to_owned(),
-                jyutping: String::default(),
-                english_sets: definitions,
-                source: EntrySource::CEDict,
-                cost });
+            let mut entry = DictionaryEntry::new(traditional, "", EntrySource::CEDict, definitions, cost);
+            entry.annotations.add_comment(comment);
+            self.entries.push(entry);
+        }
+
+        debug_log!("Read {} dictionary entries from {}", {self.entries.len() - size_at_start}, path);
+    }
+
+    /// Custom dictionaries have one entry per line, either tab separated
+    ///     traditional \t simplified \t jyutping \t definition0/definition1 \t [cost] \t [tag0,tag1]
+    /// or as json lines
+    ///     {"traditional": "..", "simplified": "..", "jyutping": "..", "definitions": [".."], "cost": 100, "tags": [".."]}
+    /// Empty lines and lines starting with '#' are ignored.
+    pub fn parse_custom(&mut self, path : &str, name : &str)
+    {
+        let size_at_start = self.entries.len();
+
+        let data = std::fs::read_to_string(path).unwrap();
+        self.parse_custom_str(&data, name);
+
+        debug_log!("Read {} custom dictionary entries from {}", {self.entries.len() - size_at_start}, path);
+    }
+
+    pub fn parse_custom_str(&mut self, data : &str, name : &str)
+    {
+        for line in data.lines()
+        {
+            if (line.trim().is_empty()) {
+                continue;
+            }
+            if (line.starts_with('#')) {
+                continue;
+            }
+
+            self.add_custom(CustomEntry::parse_line(line), name);
+        }
+    }
+
+    /// A json array of custom entries, as used for runtime user dictionaries
+    pub fn parse_custom_json(&mut self, json : &str, name : &str) -> serde_json::Result<()>
+    {
+        let entries : Vec<CustomEntry> = serde_json::from_str(json)?;
+        for custom in entries {
+            self.add_custom(custom, name);
         }
 
-        println!("Read {} dictionary entries from {}", {self.entries.len() - size_at_start}, path);
+        Ok(())
     }
 
-    pub fn apply_additional_heuristics(&mut self)
+    pub fn add_custom(&mut self, custom : CustomEntry, name : &str)
     {
-        for e in &mut self.entries
+        // Tags are shown the same way CC-Canto marks (Cantonese) definitions
+        let prefix = if (custom.tags.is_empty()) {
+            String::new()
+        }
+        else {
+            format!("({}) ", custom.tags.join(", "))
+        };
+
+        let mut definitions = StringVecSet::default();
+        for def in &custom.definitions
         {
-            // No jyutping, probably not a good entry
-            if (e.jyutping.is_empty())
-            {
-                e.cost += 10_000;
+            let def = def.trim();
+            if (def.len() == 0) {
+                continue;
             }
+
+            definitions.add(format!("{}{}", prefix, def));
         }
+
+        let cost = custom.cost.unwrap_or(DEFAULT_CUSTOM_COST);
+        let mut entry = DictionaryEntry::new(&custom.traditional, &custom.jyutping, EntrySource::Custom(name.to_owned()), definitions, cost);
+        entry.explicit_cost = custom.cost.is_some();
+        self.entries.push(entry);
     }
-}
 
-enum Heuristic
-{
-    ContainsTerms(&'static [&'static str]),
-    DoesNotContainTerms(&'static [&'static str]),
-}
+    pub fn apply_additional_heuristics(&mut self, heuristics: &HeuristicsConfig)
+    {
+        heuristics.apply(&mut self.entries);
+    }
 
-const HEURISTICS : &[(Heuristic, u32)] = &[
-    (Heuristic::ContainsTerms(&["abbr."]), 5_000),
-    (Heuristic::DoesNotContainTerms(&["M:", "CL:"]), 5_000),
-    (Heuristic::ContainsTerms(&["Surname", "surname"]), 2_000),
-    (Heuristic::DoesNotContainTerms(&["(Cantonese)"]), 2_000),
-    (Heuristic::ContainsTerms(&["Confucius"]), 5_000),
-    (Heuristic::ContainsTerms(&["Dynasty", "Dynasties"]), 5_000),
-    (Heuristic::ContainsTerms(&["(Buddhism)"]), 5_000),
-];
+    /// Unify entries with the same traditional and jyutping, which often appear in
+    /// both CC-CEDICT and CC-Canto. Definitions stay grouped by source and the cheaper cost wins.
+    /// Run after heuristics so the costs being compared are final.
+    pub fn merge_duplicates(&mut self)
+    {
+        let size_at_start = self.entries.len();
 
-fn cost_heuristic(english_definitions: &[String]) -> u32
-{
-    //let from_number_of_defs: u32 = 1000 - english_definitions.len().min(10) as u32 * 100;
+        let mut merged: Vec<DictionaryEntry> = Vec::with_capacity(self.entries.len());
+        let mut key_to_index: BTreeMap<(String, String), usize> = BTreeMap::new();
 
-    let mut cost = 0;
+        for e in self.entries.drain(..) {
+            // Without a reading there's nothing to say two entries are the same word
+            if (e.jyutping.is_empty()) {
+                merged.push(e);
+                continue;
+            }
 
-    for (heuristic, c) in HEURISTICS {
-        match heuristic {
-            Heuristic::ContainsTerms(terms) => {
-                if (matches_terms(terms, english_definitions)) {
-                    cost += c;
-                }
-            },
-            Heuristic::DoesNotContainTerms(terms) => {
-                if (!matches_terms(terms, english_definitions)) {
-                    cost += c;
-                }
+            let key = (e.traditional.clone(), e.jyutping.to_ascii_lowercase());
+            if let Some(&i) = key_to_index.get(&key) {
+                merged[i].merge(e);
+            }
+            else {
+                key_to_index.insert(key, merged.len());
+                merged.push(e);
             }
         }
+
+        // Sources in a fixed order rather than whichever was read first
+        for e in &mut merged {
+            e.groups.sort_by(|x, y| x.source.cmp(&y.source));
+        }
+
+        self.entries = merged;
+        debug_log!("Merged {} duplicate entries", size_at_start - self.entries.len());
+    }
+
+    /// Pull classifiers, usage labels and cross references out of the definitions, see annotations::extract_annotations
+    pub fn extract_annotations(&mut self)
+    {
+        extract_annotations(&mut self.entries);
     }
 
-    cost
+    /// Run after merging so each sentence is linked to the final entries
+    pub fn link_examples(&mut self)
+    {
+        if (!self.examples.is_empty()) {
+            link_examples(&mut self.entries, &self.examples);
+        }
+    }
 }
 
-fn matches_terms(needles: &[&str], heystacks: &[String]) -> bool {
-    for needle in needles {
-        for heystack in heystacks {
-            if (heystack.contains(needle)) {
-                return true;
-            }
+/// Hash of everything from_data_dir_with_custom reads, in the order it reads them
+pub fn hash_inputs(data_path: &str, custom_paths: &[String], heuristics: &HeuristicsConfig) -> u64
+{
+    let mut hasher = InputHasher::new();
+    for name in DATA_DIR_INPUTS {
+        let path = format!("{}/{}", data_path, name);
+        if std::path::Path::new(&path).exists() {
+            hasher.add_file(&path);
+        }
+        else {
+            hasher.add(&[]);
         }
     }
 
-    false
+    for path in custom_paths {
+        hasher.add_file(path);
+    }
+
+    hasher.add(heuristics.to_json().as_bytes());
+    hasher.finish()
+}
+
+/// Custom dictionaries are named after their file, "glossary/legal.tsv" is "legal"
+pub fn custom_source_name(path: &str) -> String
+{
+    std::path::Path::new(path).file_stem().unwrap().to_string_lossy().into_owned()
 }
 
-#[derive(Debug)]
+/// One line of a custom dictionary, see Builder::parse_custom
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CustomEntry
+{
+    pub traditional: String,
+    pub simplified: String,
+    pub jyutping: String,
+    pub definitions: Vec<String>,
+    pub cost: Option<u32>,
+    pub tags: Vec<String>,
+}
+
+impl CustomEntry
+{
+    pub fn parse_line(line: &str) -> Self
+    {
+        if (line.starts_with('{')) {
+            return serde_json::from_str(line).unwrap_or_else(|e| panic!("Could not parse custom entry '{}': {}", line, e));
+        }
+
+        let mut parts = line.split('\t').map(|x| x.trim());
+        let traditional = parts.next().unwrap().to_owned();
+        let simplified = parts.next().unwrap_or_default().to_owned();
+        let jyutping = parts.next().unwrap_or_default().to_owned();
+        let definitions = parts.next().unwrap_or_default().split('/').map(|x| x.to_owned()).collect();
+
+        let cost = parts.next().filter(|x| !x.is_empty())
+            .map(|x| x.parse().unwrap_or_else(|_| panic!("Bad cost '{}' for custom entry {}", x, traditional)));
+
+        let tags = parts.next().unwrap_or_default().split(',').map(|x| x.trim()).filter(|x| !x.is_empty()).map(|x| x.to_owned()).collect();
+
+        Self {
+            traditional,
+            simplified,
+            jyutping,
+            definitions,
+            cost,
+            tags,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct DictionaryEntry
 {
     pub cost: u32,
     pub traditional: String,
     pub jyutping: String,
-    pub english_sets: StringVecSet,
+
+    // One group per source, more than one after merging duplicates
+    pub groups: Vec<DefinitionGroup>,
+
+    // Set when the cost comes from the Cantonese word frequency list
+    pub word_frequency: Option<u32>,
+
+    // Set when a custom dictionary gave the cost, heuristics only apply overrides to it
+    pub explicit_cost: bool,
+
+    pub annotations: EntryAnnotations,
+
+    // Indexes into Builder::examples
+    pub examples: Vec<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DefinitionGroup
+{
     pub source: EntrySource,
+    pub english_sets: StringVecSet,
+}
+
+impl DictionaryEntry
+{
+    pub fn new(traditional: &str, jyutping: &str, source: EntrySource, english_sets: StringVecSet, cost: u32) -> Self {
+        Self {
+            cost,
+            traditional: traditional.to_owned(),
+            jyutping: jyutping.to_owned(),
+            groups: vec![DefinitionGroup {
+                source,
+                english_sets,
+            }],
+            word_frequency: None,
+            explicit_cost: false,
+            annotations: EntryAnnotations::default(),
+            examples: Vec::new(),
+        }
+    }
+
+    pub fn has_source(&self, source: &EntrySource) -> bool {
+        self.groups.iter().any(|x| x.source == *source)
+    }
+
+    pub fn definitions(&self) -> impl Iterator<Item = &String> {
+        self.groups.iter().flat_map(|x| x.english_sets.inner.iter())
+    }
+
+    /// Total order on everything but cost, used to break ties between entries of equal cost
+    pub fn cmp_content(&self, other: &DictionaryEntry) -> std::cmp::Ordering {
+        self.traditional.cmp(&other.traditional)
+            .then_with(|| self.jyutping.cmp(&other.jyutping))
+            .then_with(|| self.groups.iter().map(|x| (&x.source, &x.english_sets.inner))
+                .cmp(other.groups.iter().map(|x| (&x.source, &x.english_sets.inner))))
+    }
+
+    pub fn merge(&mut self, other: DictionaryEntry) {
+        // Keys ignore the case of jyutping, pick a spelling that doesn't depend on input order.
+        // Readings are normally lowercase, then the ASCII order breaks ties.
+        let canonical = |x: &str| (x.chars().any(|c| c.is_ascii_uppercase()), x.to_owned());
+        if (canonical(&other.jyutping) < canonical(&self.jyutping)) {
+            self.jyutping = other.jyutping.clone();
+        }
+
+        self.cost = self.cost.min(other.cost);
+        self.word_frequency = self.word_frequency.max(other.word_frequency);
+
+        for comment in &other.annotations.comments {
+            self.annotations.add_comment(comment);
+        }
+
+        for group in other.groups {
+            if let Some(existing) = self.groups.iter_mut().find(|x| x.source == group.source) {
+                existing.english_sets.extend(group.english_sets);
+            }
+            else {
+                self.groups.push(group);
+            }
+        }
+    }
 }
 
 #[derive(Debug, Default)]
@@ -285,7 +610,7 @@ impl TraditionalToJyutping
             map.add(traditional, &jyutping);
         }
 
-        println!("Read {} jyutping romanisations", {map.inner.len()});
+        debug_log!("Read {} jyutping romanisations", {map.inner.len()});
         map
     }
 }
@@ -331,12 +656,33 @@ impl TraditionalToFrequencies
         }
     }
 
-    pub fn add_canto(&mut self, characters: &str) {
-        for c in characters.chars() {
-            // HACK
-            //self.inner.entry(c).or_insert(FrequencyData { count: 1, frequency: 0.001, cost: 2.0, index: 10_000 });
-            self.inner.entry(c).or_insert(FrequencyData { count: 1, frequency: 0.001, cost: 10_000, index: 10_000 });
+    /// Characters missing from the Mandarin list take their frequency across the Cantonese words
+    pub fn add_canto(&mut self, word_frequencies: &WordFrequencies) {
+        let mut counts: BTreeMap<char, u64> = BTreeMap::new();
+        let mut total : u64 = 0;
+        for (word, x) in &word_frequencies.inner {
+            for c in word.chars() {
+                *counts.entry(c).or_default() += x.count as u64;
+                total += x.count as u64;
+            }
         }
+
+        let mut added = 0;
+        for (c, count) in counts {
+            if (self.inner.contains_key(&c)) {
+                continue;
+            }
+
+            let frequency = count as f32 / total.max(1) as f32;
+            let cost = -1_000.0 * frequency.ln();
+            let cost = cost.clamp(1.0, MAX_STATIC_COST_F) as u32;
+
+            let index = self.inner.len() as i32 + 1;
+            self.inner.insert(c, FrequencyData { count: count.min(i32::MAX as u64) as i32, frequency, cost, index });
+            added += 1;
+        }
+
+        debug_log!("Added {} Cantonese character frequencies", added);
     }
 
     pub fn parse(path : &str) -> Self
@@ -379,10 +725,249 @@ impl TraditionalToFrequencies
             inner.insert(character.chars().next().unwrap(), data);
         }
 
-        println!("Read {} character frequencies", {inner.len()});
+        debug_log!("Read {} character frequencies", {inner.len()});
 
         Self {
             inner,
         }
     }
 }
+
+#[derive(Debug, Clone, Copy)]
+pub struct WordFrequencyData
+{
+    pub count : u32,
+    pub cost : u32,
+}
+
+/// Word level counts from a Cantonese corpus.
+#[derive(Debug, Default)]
+pub struct WordFrequencies
+{
+    inner : BTreeMap<String, WordFrequencyData>,
+}
+
+impl WordFrequencies
+{
+    pub fn get(&self, word : &str) -> Option<WordFrequencyData> {
+        self.inner.get(word).copied()
+    }
+
+    pub fn parse(path : &str) -> Self
+    {
+        let data = std::fs::read_to_string(path).unwrap();
+        let word_frequencies = Self::parse_str(&data);
+        debug_log!("Read {} word frequencies", {word_frequencies.inner.len()});
+        word_frequencies
+    }
+
+    pub fn parse_str(data : &str) -> Self
+    {
+        let mut counts: BTreeMap<String, u32> = BTreeMap::new();
+        let mut total : u64 = 0;
+
+        for line in data.lines()
+        {
+            if (line.trim().is_empty()) {
+                continue;
+            }
+            if (line.starts_with('#')) {
+                continue;
+            }
+
+            // Expect form
+            // word \t count
+
+            let mut split = line.split_whitespace();
+            let word = split.next().unwrap();
+            let count : u32 = split.next().unwrap_or_else(|| panic!("No count for '{}'", word)).parse().unwrap();
+
+            total += count as u64;
+            *counts.entry(word.to_owned()).or_default() += count;
+        }
+
+        let mut inner = BTreeMap::new();
+        for (word, count) in counts {
+            // Same scale as character frequencies, but for the whole word
+            let frequency = count as f32 / total.max(1) as f32;
+            let cost = -1_000.0 * frequency.ln();
+            let cost = cost.clamp(1.0, MAX_STATIC_COST_F) as u32;
+
+            inner.insert(word, WordFrequencyData {
+                count,
+                cost,
+            });
+        }
+
+        Self {
+            inner,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_frequencies() {
+        let word_frequencies = WordFrequencies::parse_str("# comment\n嘅\t900\n佢哋 90\n\n咗\t9\n咗\t1\n");
+
+        let ge = word_frequencies.get("嘅").unwrap();
+        let keoi_dei = word_frequencies.get("佢哋").unwrap();
+        let zo = word_frequencies.get("咗").unwrap();
+
+        assert_eq!(10, zo.count);
+        assert!(ge.cost < keoi_dei.cost);
+        assert!(keoi_dei.cost < zo.cost);
+        assert!(word_frequencies.get("老師").is_none());
+    }
+
+    #[test]
+    fn word_frequency_overrides_character_cost() {
+        let mut builder = Builder::default();
+        for traditional in ["佢哋", "老師"] {
+            builder.entries.push(DictionaryEntry::new(traditional, "", EntrySource::CEDict, StringVecSet::default(), 2 * MAX_STATIC_COST));
+        }
+
+        builder.apply_word_frequencies(&WordFrequencies::parse_str("佢哋\t1\n嘅\t1\n"));
+
+        // Half of the corpus
+        assert_eq!(693, builder.entries[0].cost);
+        assert_eq!(Some(1), builder.entries[0].word_frequency);
+
+        // Falls back to the character cost
+        assert_eq!(2 * MAX_STATIC_COST, builder.entries[1].cost);
+        assert_eq!(None, builder.entries[1].word_frequency);
+    }
+
+    #[test]
+    fn merge_duplicates() {
+        let mut builder = Builder::default();
+        builder.entries.push(DictionaryEntry::new("虎", "fu2", EntrySource::CEDict, StringVecSet::single("tiger".to_owned()), 2_000));
+        builder.entries.push(DictionaryEntry::new("一並", "jat1 bing6", EntrySource::CEDict, StringVecSet::single("together".to_owned()), 3_000));
+        builder.entries.push(DictionaryEntry::new("虎", "fu2", EntrySource::CCanto, StringVecSet::single("(slang) wife".to_owned()), 1_000));
+        builder.entries.push(DictionaryEntry::new("虎", "Fu2", EntrySource::CEDict, StringVecSet::single("surname Hu".to_owned()), 5_000));
+        builder.entries.push(DictionaryEntry::new("虎", "fu1", EntrySource::CCanto, StringVecSet::single("other reading".to_owned()), 0));
+
+        builder.merge_duplicates();
+
+        assert_eq!(3, builder.entries.len());
+
+        let tiger = &builder.entries[0];
+        assert_eq!(1_000, tiger.cost);
+        assert_eq!("fu2", tiger.jyutping);
+        assert_eq!(2, tiger.groups.len());
+        assert_eq!(EntrySource::CEDict, tiger.groups[0].source);
+        assert_eq!(vec!["tiger", "surname Hu"], tiger.groups[0].english_sets.inner);
+        assert_eq!(EntrySource::CCanto, tiger.groups[1].source);
+        assert_eq!(vec!["(slang) wife"], tiger.groups[1].english_sets.inner);
+
+        assert_eq!("一並", builder.entries[1].traditional);
+        assert_eq!("fu1", builder.entries[2].jyutping);
+
+        // Unannotated entries have no reading to compare
+        let mut builder = Builder::default();
+        builder.entries.push(DictionaryEntry::new("乾", "", EntrySource::CEDict, StringVecSet::single("dry".to_owned()), 1_000));
+        builder.entries.push(DictionaryEntry::new("乾", "", EntrySource::CEDict, StringVecSet::single("heaven".to_owned()), 1_000));
+        builder.merge_duplicates();
+        assert_eq!(2, builder.entries.len());
+
+        // Neither reading is lowercase, ASCII order breaks the tie
+        let mut builder = Builder::default();
+        builder.entries.push(DictionaryEntry::new("路", "Lou6", EntrySource::CEDict, StringVecSet::single("Lu".to_owned()), 1_000));
+        builder.entries.push(DictionaryEntry::new("路", "LOU6", EntrySource::CEDict, StringVecSet::single("LU".to_owned()), 1_000));
+        builder.merge_duplicates();
+        assert_eq!("LOU6", builder.entries[0].jyutping);
+
+        // A lowercase reading wins regardless of order
+        for readings in [["lou6", "Lou6", "LOU6"], ["Lou6", "LOU6", "lou6"]] {
+            let mut builder = Builder::default();
+            for jyutping in readings {
+                builder.entries.push(DictionaryEntry::new("路", jyutping, EntrySource::CEDict, StringVecSet::single(jyutping.to_owned()), 1_000));
+            }
+
+            builder.merge_duplicates();
+            assert_eq!(1, builder.entries.len());
+            assert_eq!("lou6", builder.entries[0].jyutping, "{:?}", readings);
+        }
+    }
+
+    #[test]
+    fn ccanto_falls_back_to_character_frequency() {
+        let word_frequencies = WordFrequencies::parse_str("佢哋\t900\n嘅\t100\n");
+        let mut trad_to_frequency = TraditionalToFrequencies::default();
+        trad_to_frequency.add_canto(&word_frequencies);
+
+        let mut builder = Builder::default();
+        builder.parse_ccanto_str(concat!(
+            "佢哋 佢哋 [qu2 di4] {keoi5 dei6} /they/\n",
+            "佢 佢 [qu2] {keoi5} /he; she/\n",
+            "嘢 嘢 [ye3] {je5} /thing/\n",
+        ), &trad_to_frequency);
+        builder.apply_word_frequencies(&word_frequencies);
+
+        let keoi_dei = &builder.entries[0];
+        assert_eq!(Some(900), keoi_dei.word_frequency);
+        assert_eq!(word_frequencies.get("佢哋").unwrap().cost, keoi_dei.cost);
+
+        // No word frequency, but 佢 is common across the words and 嘢 isn't in either list
+        let keoi = &builder.entries[1];
+        let je = &builder.entries[2];
+        assert_eq!(None, keoi.word_frequency);
+        assert_eq!(trad_to_frequency.get_or_default('佢').cost, keoi.cost);
+        assert!(keoi.cost < MAX_STATIC_COST);
+        assert_eq!(MAX_STATIC_COST, je.cost);
+
+        // The heuristics add the same base cost to both
+        HeuristicsConfig::default().apply(&mut builder.entries);
+        assert!(builder.entries[1].cost < builder.entries[2].cost);
+    }
+
+    #[test]
+    fn parse_custom() {
+        let mut builder = Builder::default();
+        builder.entries.push(DictionaryEntry::new("律師", "leot6 si1", EntrySource::CEDict, StringVecSet::single("lawyer".to_owned()), 4_000));
+
+        builder.parse_custom_str(concat!(
+            "# traditional\tsimplified\tjyutping\tdefinitions\tcost\ttags\n",
+            "律師\t律师\tleot6 si1\tsolicitor/barrister\t\tlegal\n",
+            "\n",
+            "{\"traditional\": \"傳票\", \"jyutping\": \"cyun4 piu3\", \"definitions\": [\"summons\"], \"cost\": 500}\n",
+        ), "legal");
+
+        assert_eq!(3, builder.entries.len());
+
+        let solicitor = &builder.entries[1];
+        assert_eq!(DEFAULT_CUSTOM_COST, solicitor.cost);
+        assert!(!solicitor.explicit_cost);
+        assert!(solicitor.has_source(&EntrySource::Custom("legal".to_owned())));
+        assert_eq!(vec!["(legal) solicitor", "(legal) barrister"], solicitor.groups[0].english_sets.inner);
+
+        let summons = &builder.entries[2];
+        assert_eq!(500, summons.cost);
+        assert!(summons.explicit_cost);
+        assert_eq!("cyun4 piu3", summons.jyutping);
+
+        // Custom definitions join the existing entry
+        builder.merge_duplicates();
+        assert_eq!(2, builder.entries.len());
+        assert_eq!(2, builder.entries[0].groups.len());
+
+        assert_eq!("legal", custom_source_name("glossary/legal.tsv"));
+    }
+
+    #[test]
+    fn custom_cost_survives_heuristics() {
+        let data_path = concat!(env!("CARGO_MANIFEST_DIR"), "/../test");
+        let custom_path = std::env::temp_dir().join(format!("jyp_custom_cost_{}.tsv", std::process::id()));
+        std::fs::write(&custom_path, "傳票	传票	cyun4 piu3	summons	100	\n").unwrap();
+        let custom_paths = vec![custom_path.to_string_lossy().into_owned()];
+
+        let builder = Builder::from_data_dir_with_custom(data_path, &custom_paths, &HeuristicsConfig::from_data_dir(data_path));
+        std::fs::remove_file(&custom_path).unwrap();
+
+        let summons = builder.entries.iter().find(|x| x.traditional == "傳票").unwrap();
+        assert_eq!(100, summons.cost);
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::data_reader::DataReader;
+use crate::data_writer::DataWriter;
+
+/// Per character metadata from a Unihan style file.
+/// Radical 0 means the radical is unknown.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[cfg_attr(feature = "tsify", derive(tsify::Tsify))]
+pub struct CharacterInfo {
+    // Kangxi radical number 1-214
+    pub radical: u8,
+    // Strokes in addition to the radical
+    pub additional_strokes: u8,
+    pub total_strokes: u8,
+    // All Cantonese readings, most common first
+    pub readings: Vec<String>,
+    pub components: Vec<char>,
+}
+
+impl CharacterInfo {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    pub fn write<T : std::io::Write>(&self, writer: &mut DataWriter<T>) -> std::io::Result<()> {
+        writer.write_u8(self.radical)?;
+        writer.write_u8(self.additional_strokes)?;
+        writer.write_u8(self.total_strokes)?;
+
+        writer.write_u8(self.readings.len() as u8)?;
+        for r in &self.readings {
+            writer.write_string(r)?;
+        }
+
+        writer.write_u8(self.components.len() as u8)?;
+        for c in &self.components {
+            writer.write_utf8(*c)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read(reader: &mut DataReader) -> Self {
+        let mut info = Self {
+            radical: reader.read_u8(),
+            additional_strokes: reader.read_u8(),
+            total_strokes: reader.read_u8(),
+            ..Default::default()
+        };
+
+        let reading_count = reader.read_u8();
+        for _ in 0..reading_count {
+            info.readings.push(reader.read_string().to_owned());
+        }
+
+        let component_count = reader.read_u8();
+        for _ in 0..component_count {
+            info.components.push(reader.read_utf8_char());
+        }
+
+        info
+    }
+}
+
+/// Kangxi radicals block, radical n is at U+2F00 + n - 1
+pub fn radical_char(radical: u8) -> Option<char> {
+    if (radical == 0 || radical > 214) {
+        return None;
+    }
+
+    char::from_u32(0x2F00 + radical as u32 - 1)
+}
+
+/// Search characters by radical and stroke counts, unspecified fields match anything
+#[derive(Debug, Clone, Default, Deserialize)]
+#[cfg_attr(feature = "tsify", derive(tsify::Tsify))]
+#[serde(default)]
+pub struct CharacterQuery {
+    pub radical: Option<u8>,
+    pub additional_strokes: Option<u8>,
+    pub total_strokes: Option<u8>,
+}
+
+impl CharacterQuery {
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    pub fn matches(&self, info: &CharacterInfo) -> bool {
+        if (info.is_empty()) {
+            return false;
+        }
+
+        self.radical.is_none_or(|x| x == info.radical)
+            && self.additional_strokes.is_none_or(|x| x == info.additional_strokes)
+            && self.total_strokes.is_none_or(|x| x == info.total_strokes)
+    }
+}
+
+/// Character metadata read at build time
+#[derive(Debug, Default)]
+pub struct CharacterInfoTable {
+    pub inner: BTreeMap<char, CharacterInfo>,
+}
+
+impl CharacterInfoTable {
+    pub fn get(&self, c: char) -> Option<&CharacterInfo> {
+        self.inner.get(&c)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    pub fn parse(path: &str) -> Self {
+        let data = std::fs::read_to_string(path).unwrap();
+        let table = Self::parse_str(&data);
+        debug_log!("Read character info for {} characters from {}", table.inner.len(), path);
+        table
+    }
+
+    /// Unihan style, one field per line
+    ///     U+864E \t kRSUnicode \t 141.2
+    /// Understands kRSUnicode, kTotalStrokes, kCantonese and kIDS (an ideographic description sequence
+    /// that components are taken from). Other fields are ignored.
+    pub fn parse_str(data: &str) -> Self {
+        let mut inner: BTreeMap<char, CharacterInfo> = BTreeMap::new();
+
+        for line in data.lines() {
+            if (line.trim().is_empty() || line.starts_with('#')) {
+                continue;
+            }
+
+            let mut parts = line.split('\t');
+            let codepoint = parts.next().unwrap().trim();
+            let field = parts.next().unwrap_or_else(|| panic!("No field for '{}'", codepoint)).trim();
+            let value = parts.next().unwrap_or_else(|| panic!("No value for '{}' {}", codepoint, field)).trim();
+
+            let hex = codepoint.strip_prefix("U+").unwrap_or_else(|| panic!("Unexpected codepoint '{}'", codepoint));
+            let c = char::from_u32(u32::from_str_radix(hex, 16).unwrap()).unwrap();
+
+            match field {
+                "kRSUnicode" => {
+                    // Can have several values, the first is the main one.
+                    // An apostrophe marks a simplified form of the radical.
+                    let first = value.split_whitespace().next().unwrap();
+                    let (radical, additional) = first.split_once('.').unwrap_or_else(|| panic!("Unexpected kRSUnicode '{}'", value));
+                    let info = inner.entry(c).or_default();
+                    info.radical = radical.trim_end_matches('\'').parse().unwrap();
+                    info.additional_strokes = additional.parse::<i32>().unwrap().max(0) as u8;
+                }
+                "kTotalStrokes" => {
+                    let first = value.split_whitespace().next().unwrap();
+                    inner.entry(c).or_default().total_strokes = first.parse().unwrap();
+                }
+                "kCantonese" => {
+                    inner.entry(c).or_default().readings = value.split_whitespace().map(|x| x.to_owned()).collect();
+                }
+                "kIDS" => {
+                    // Description characters ⿰⿱.. are in U+2FF0-U+2FFF
+                    inner.entry(c).or_default().components = value.chars()
+                        .filter(|x| !('\u{2FF0}'..='\u{2FFF}').contains(x) && !x.is_whitespace())
+                        .collect();
+                }
+                _ => {}
+            }
+        }
+
+        Self {
+            inner,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_unihan() {
+        let table = CharacterInfoTable::parse_str(concat!(
+            "# Unihan\n",
+            "U+864E\tkRSUnicode\t141.2\n",
+            "U+864E\tkTotalStrokes\t8\n",
+            "U+864E\tkCantonese\tfu2\n",
+            "U+864E\tkIDS\t⿸虍儿\n",
+            "U+864E\tkDefinition\ttiger; brave, fierce\n",
+            "U+8BF4\tkRSUnicode\t149'.7\n",
+            "U+8BF4\tkCantonese\tseoi3 syut3\n",
+        ));
+
+        assert_eq!(2, table.inner.len());
+        assert_eq!(&CharacterInfo {
+            radical: 141,
+            additional_strokes: 2,
+            total_strokes: 8,
+            readings: vec!["fu2".to_owned()],
+            components: vec!['虍', '儿'],
+        }, table.get('虎').unwrap());
+
+        let simplified = table.get('说').unwrap();
+        assert_eq!(149, simplified.radical);
+        assert_eq!(vec!["seoi3", "syut3"], simplified.readings);
+
+        assert_eq!(Some('⾍'), radical_char(142));
+        assert_eq!(None, radical_char(0));
+
+        let query = CharacterQuery::from_json(r#"{"radical": 141}"#).unwrap();
+        assert!(query.matches(table.get('虎').unwrap()));
+        assert!(!query.matches(simplified));
+        assert!(!CharacterQuery::default().matches(&CharacterInfo::default()));
+    }
+}
@@ -1,26 +1,84 @@
 use core::str;
-use std::{collections::BTreeSet, io::BufWriter};
+use std::{collections::{BTreeMap, BTreeSet}, io::BufWriter};
 use std::io::Write;
 
 use serde::Serialize;
 
 use crate::EntrySource;
-use crate::{data_reader::DataReader, data_writer::DataWriter, jyutping_splitter::JyutpingSplitter, builder::Builder};
+use crate::annotations::{CrossReferenceKind, EntryAnnotations};
+use crate::examples::{ExampleSentence, ExampleStore};
+use crate::english_store::EnglishStore;
+use crate::entry_codec::EntryCodec;
+use crate::stats::DictionaryStats;
+use crate::character_info::{CharacterInfo, CharacterInfoTable, CharacterQuery};
+use crate::{data_reader::DataReader, data_writer::DataWriter, jyutping_splitter::{JyutpingSplitter, parse_jyutping_tone}, builder::Builder};
+
+/// Why CompiledDictionary::append_chunk rejected a chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkError
+{
+    BadHeader,
+    Version(u32),
+    // Built from different inputs than the loaded dictionary, eg a stale cached file
+    DifferentBuild,
+    NotContinuing { expected: usize, first_entry: usize },
+    ExamplesAttached,
+    NotInCostOrder,
+}
+
+impl std::fmt::Display for ChunkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChunkError::BadHeader => write!(f, "Not a dictionary chunk"),
+            ChunkError::Version(version) => write!(f, "Chunk is version {}, expected {}", version, CURRENT_VERSION),
+            ChunkError::DifferentBuild => write!(f, "Chunk is from a different build"),
+            ChunkError::NotContinuing { expected, first_entry } => write!(f, "Chunk starts at entry {}, expected {}", first_entry, expected),
+            ChunkError::ExamplesAttached => write!(f, "Examples must be attached after every chunk"),
+            ChunkError::NotInCostOrder => write!(f, "Chunk entries are not in cost order"),
+        }
+    }
+}
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct CompiledDictionary
 {
     pub character_store : CharacterStore,
     pub jyutping_store : JyutpingStore,
 
+    // Names of custom dictionaries, indexed by CompiledDictionaryEntry::custom_source
+    pub custom_sources : Vec<String>,
+
     pub entries : Vec<CompiledDictionaryEntry>,
-    pub english_data: Vec<u8>,
-    pub english_data_starts: Vec<u32>,
+
+    // Definitions numbered by CompiledDictionaryEntry::english_start..english_end
+    pub english: EnglishStore,
+
+    // Cross references resolved to entries, sorted by source_entry
+    pub links: Vec<EntryLink>,
+
+    // Only present when built with a sentence corpus, usually shipped as a separate file
+    pub examples: Option<ExampleStore>,
+
+    // As read from the file, needed to decode chunks appended to it
+    pub entry_codec: EntryCodec,
+
+    // Builder::input_hash of the build, chunks must come from the same one
+    pub input_hash: u64,
+}
+
+/// "variant of X" and similar references from one entry to another
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EntryLink
+{
+    pub source_entry: u32,
+    pub target_entry: u32,
+    pub kind: CrossReferenceKind,
 }
 
 pub const FILE_HEADER: &[u8] = b"jyp_dict";
-pub const ENGLISH_BLOB_HEADER: &[u8] = b"en_data_";
-pub const CURRENT_VERSION: u32 = 8;
+pub const LINKS_HEADER: &[u8] = b"links___";
+pub const CHUNK_HEADER: &[u8] = b"jyp_chnk";
+pub const CURRENT_VERSION: u32 = 18;
 
 impl CompiledDictionary {
     pub fn from_builder(mut dict : Builder) -> Self {
@@ -50,30 +108,53 @@ impl CompiledDictionary {
         let all_characters_list : Vec<char> = all_characters.into_iter().collect();
         let all_jyutping_words_list : Vec<String> = all_jyutping_words.into_iter().collect();
 
-        let character_store = CharacterStore::from_chars(all_characters_list);
+        let mut character_store = CharacterStore::from_chars(all_characters_list);
+        if (!dict.character_info.is_empty()) {
+            character_store.set_info(&dict.character_info);
+        }
         let jyutping_store = JyutpingStore::from_strings(all_jyutping_words_list);
 
+        let mut custom_sources_set : BTreeSet<String> = BTreeSet::new();
+        for entry in dict.entries.iter() {
+            for group in &entry.groups {
+                if let EntrySource::Custom(name) = &group.source {
+                    custom_sources_set.insert(name.clone());
+                }
+            }
+        }
+
+        let custom_sources : Vec<String> = custom_sources_set.into_iter().collect();
+        assert!(custom_sources.len() < 256);
+        let custom_source_index = |source: &EntrySource| -> u8 {
+            match source {
+                EntrySource::Custom(name) => custom_sources.iter().position(|x| x == name).unwrap() as u8,
+                _ => 0,
+            }
+        };
+
         debug_log!("Individual characters {}, Individual jyutping words {}", character_store.characters.len(), jyutping_store.base_strings.len());
         //println!("{:#?}", character_store.characters);
         //println!("{:#?}", jyutping_store.base_strings);
 
-        dict.entries.sort_by(|x, y| x.cost.cmp(&y.cost));
+        // Ties broken on content so the order doesn't depend on how sources were read
+        dict.entries.sort_by(|x, y| x.cost.cmp(&y.cost).then_with(|| x.cmp_content(y)));
+
+        // References point at the cheapest entry with the target's characters
+        let mut traditional_to_entry : BTreeMap<&str, u32> = BTreeMap::new();
+        for (i, entry) in dict.entries.iter().enumerate() {
+            traditional_to_entry.entry(entry.traditional.as_str()).or_insert(i as u32);
+        }
 
         let mut entries = Vec::new();
+        let mut links = Vec::new();
 
-        let mut english_data = Vec::new();
-        let mut english_data_starts = Vec::new();
+        let mut definitions : Vec<&str> = Vec::new();
 
-        for entry in &dict.entries
+        for (entry_id, entry) in dict.entries.iter().enumerate()
         {
             let mut flags: u8 = 0;
-            match entry.source {
-                EntrySource::CEDict => {
-                    flags |= FLAG_SOURCE_CEDICT;
-                }
-                EntrySource::CCanto => {
-                    flags |= FLAG_SOURCE_CCCANTO;
-                }
+            for group in &entry.groups {
+                flags |= group.source.flag();
             }
 
             let mut char_indexes = Vec::new();
@@ -89,13 +170,59 @@ impl CompiledDictionary {
                 mapped_jyutping.push(jyutping_store.get(word).unwrap());
             }
 
-            let english_start = english_data_starts.len();
-            for definition in &entry.english_sets.inner
+            let english_start = definitions.len();
+            let mut source_groups = Vec::new();
+            for group in &entry.groups
             {
-                english_data_starts.push(english_data.len() as u32);
-                english_data.extend_from_slice(definition.as_bytes());
+                for definition in &group.english_sets.inner
+                {
+                    definitions.push(definition);
+                }
+
+                let definition_count = group.english_sets.inner.len();
+                assert!(definition_count <= u8::MAX as usize, "{} has {} definitions from one source", entry.traditional, definition_count);
+
+                source_groups.push(SourceGroup {
+                    source: group.source.flag(),
+                    custom_source: custom_source_index(&group.source),
+                    definition_count: definition_count as u8,
+                });
             }
-            let english_end = english_data_starts.len();
+            let english_end = definitions.len();
+
+            // Single source entries get their one group implicitly from the flags
+            let mut custom_source = 0;
+            if (source_groups.len() == 1) {
+                custom_source = source_groups[0].custom_source;
+                source_groups.clear();
+            }
+            else {
+                flags |= FLAG_MERGED_SOURCES;
+            }
+
+            // Resolved references move to the link table, anything else stays as text
+            let mut entry_annotations = entry.annotations.clone();
+            entry_annotations.cross_references.retain(|x| {
+                match traditional_to_entry.get(x.characters.as_str()) {
+                    Some(&target_entry) if target_entry != entry_id as u32 => {
+                        links.push(EntryLink {
+                            source_entry: entry_id as u32,
+                            target_entry,
+                            kind: x.kind,
+                        });
+                        false
+                    },
+                    _ => true,
+                }
+            });
+
+            let annotations = if (entry_annotations.is_empty()) {
+                None
+            }
+            else {
+                flags |= FLAG_HAS_ANNOTATIONS;
+                Some(Box::new(entry_annotations))
+            };
 
             entries.push(CompiledDictionaryEntry {
                 characters: char_indexes,
@@ -104,20 +231,52 @@ impl CompiledDictionary {
                 english_end: english_end as u32,
                 cost: entry.cost,
                 flags,
+                custom_source,
+                source_groups,
+                annotations,
             });
         }
 
-        english_data_starts.push(english_data.len() as u32);
+        let english = EnglishStore::from_definitions(&definitions);
+        debug_log!("{} definitions, {} english tokens", english.definition_count(), english.token_count());
+
+        debug_log!("Resolved {} cross reference links", links.len());
+
+        let examples = if (dict.examples.is_empty()) {
+            None
+        }
+        else {
+            Some(ExampleStore::from_entries(&dict.entries, std::mem::take(&mut dict.examples), dict.input_hash))
+        };
 
         Self {
             character_store,
             jyutping_store,
+            custom_sources,
             entries,
-            english_data,
-            english_data_starts,
+            english,
+            links,
+            examples,
+            // Only read dictionaries have chunks appended
+            entry_codec: EntryCodec::default(),
+            input_hash: dict.input_hash,
         }
     }
 
+    /// The input hash from a dictionary file's header, None if it was written by a different version
+    pub fn read_input_hash(data: &[u8]) -> Option<u64> {
+        if (data.len() < FILE_HEADER.len() + 12 || &data[..FILE_HEADER.len()] != FILE_HEADER) {
+            return None;
+        }
+
+        let mut reader = DataReader::new_at(data, FILE_HEADER.len());
+        if (reader.read_u32() != CURRENT_VERSION) {
+            return None;
+        }
+
+        Some(reader.read_u64())
+    }
+
     pub fn deserialize(reader : &mut DataReader) -> Self {
         let header = reader.read_bytes_len(8);
         debug_log!("Header '{}'", std::str::from_utf8(header).expect("Header not utf8"));
@@ -127,12 +286,21 @@ impl CompiledDictionary {
         debug_log!("Version {}", version);
         assert_eq!(CURRENT_VERSION, version);
 
+        let input_hash = reader.read_u64();
+        debug_log!("Input hash {:016x}", input_hash);
+
         let mut character_store = CharacterStore::default();
         let character_count = reader.read_u32();
         for _ in 0..character_count {
             character_store.characters.push(reader.read_utf8_char());
         }
 
+        if (reader.read_u8() != 0) {
+            for _ in 0..character_count {
+                character_store.info.push(CharacterInfo::read(reader));
+            }
+        }
+
         let mut jyutping_store = JyutpingStore::default();
         let jyutping_count = reader.read_u32();
         for _ in 0..jyutping_count {
@@ -142,64 +310,34 @@ impl CompiledDictionary {
             jyutping_store.base_strings.push(base_string);
         }
 
-        let entry_count = reader.read_u32();
-        let mut entries = Vec::with_capacity(entry_count as usize);
-
-        let mut english_start = 0;
-        let mut prev_cost = 0;
-        for _ in 0..entry_count {
-            let mut entry = CompiledDictionaryEntry {
-                flags: reader.read_u8(),
-                ..Default::default()
-            };
-
-            let char_count = reader.read_u8();
-            for _ in 0..char_count {
-                entry.characters.push(reader.read_u16());
-            }
-
-            let jyutping_count = reader.read_u8();
-            entry.jyutping.reserve(jyutping_count as usize);
-            for _ in 0..jyutping_count {
-                entry.jyutping.push(Jyutping::unpack(reader.read_u16()));
-            }
-
-            entry.english_start = english_start;
-            entry.english_end = english_start + reader.read_u8() as u32;
-            english_start = entry.english_end;
-
-            let cost_delta = reader.read_vbyte() as u32;
-            entry.cost = prev_cost + cost_delta;
-            prev_cost = entry.cost;
-
-            entries.push(entry);
+        let custom_source_count = reader.read_u32();
+        let mut custom_sources = Vec::with_capacity(custom_source_count as usize);
+        for _ in 0..custom_source_count {
+            custom_sources.push(reader.read_string().to_owned());
         }
 
-        let blob_header = reader.read_bytes_len(8);
-        debug_log!("blob_header '{}'", std::str::from_utf8(blob_header).expect("Not utf8"));
-        assert!(blob_header == ENGLISH_BLOB_HEADER);
-
-        let blob_size = reader.read_u32();
-        let english_blob = reader.read_bytes_len(blob_size as usize);
+        let entry_codec = EntryCodec::read(reader);
+        let entries = entry_codec.read_entries(reader, 0);
+        let english = EnglishStore::read(reader);
+        let links = read_links(reader);
 
-        let starts_count = reader.read_u32() as usize;
-        let mut english_data_starts = Vec::with_capacity(starts_count);
-
-        let mut prev_start = 0;
-        for _ in 0..starts_count
-        {
-            let delta = reader.read_vbyte();
-            let start = prev_start + delta;
-            prev_start = start;
-            english_data_starts.push(start as u32);
+        let examples = if (reader.read_u8() != 0) {
+            Some(ExampleStore::deserialize(reader))
         }
+        else {
+            None
+        };
 
         Self {
             character_store,
             jyutping_store,
+            custom_sources,
             entries,
-            english_data: english_blob.to_owned(),
-            english_data_starts,
+            english,
+            links,
+            examples,
+            entry_codec,
+            input_hash,
         }
     }
 
@@ -212,114 +350,200 @@ impl CompiledDictionary {
         }
     }
 
-    pub fn serialize<T : std::io::Write>(&self, writer : &mut DataWriter<T>) -> std::io::Result<()>
+    pub fn serialize<T : std::io::Write>(&self, writer : &mut DataWriter<T>) -> std::io::Result<DictionaryStats>
     {
-        println!("Writing Header");
-        writer.write_bytes(FILE_HEADER)?;
-        println!("Writing Version = {}", CURRENT_VERSION);
-        writer.write_u32(CURRENT_VERSION)?;
+        self.serialize_core(writer, self.entries.len())
+    }
 
-        {
-            let start = writer.write_len;
-            let characters_len = self.character_store.characters.len() as u32;
-            writer.write_u32(characters_len)?;
-            println!("Writing Characters, length = {}", characters_len);
+    /// The stores and the first core_entries entries, which deserialize reads as a dictionary by itself.
+    /// Entries are sorted by cost so these are the most common, the rest can follow with serialize_chunk.
+    pub fn serialize_core<T : std::io::Write>(&self, writer : &mut DataWriter<T>, core_entries : usize) -> std::io::Result<DictionaryStats>
+    {
+        assert!(core_entries <= self.entries.len());
+
+        let start = writer.write_len;
+        let mut stats = DictionaryStats::from_entries(self, 0..core_entries);
+
+        stats.measure("header", writer, |w| {
+            w.write_bytes(FILE_HEADER)?;
+            w.write_u32(CURRENT_VERSION)?;
+            w.write_u64(self.input_hash)
+        })?;
+
+        stats.measure("characters", writer, |w| {
+            w.write_u32(self.character_store.characters.len() as u32)?;
             for c in &self.character_store.characters
             {
-                writer.write_utf8(*c)?;
+                w.write_utf8(*c)?;
             }
 
-            let bytes = writer.write_len - start;
-            println!("Characters bytes = {}", bytes);
-        }
+            if (self.character_store.info.is_empty()) {
+                w.write_u8(0)?;
+            }
+            else {
+                assert_eq!(self.character_store.characters.len(), self.character_store.info.len());
+                w.write_u8(1)?;
+                for info in &self.character_store.info {
+                    info.write(w)?;
+                }
+            }
 
-        {
-            let start = writer.write_len;
+            Ok(())
+        })?;
 
-            let juytping_strings_len = self.jyutping_store.base_strings.len() as u32;
-            writer.write_u32(juytping_strings_len)?;
-            println!("Writing Jyutping, length = {}", juytping_strings_len);
+        stats.measure("jyutping", writer, |w| {
+            w.write_u32(self.jyutping_store.base_strings.len() as u32)?;
             for j in &self.jyutping_store.base_strings
             {
-                writer.write_string(j)?;
+                w.write_string(j)?;
             }
 
-            let bytes = writer.write_len - start;
-            println!("Jyutping bytes = {}", bytes);
-        }
+            Ok(())
+        })?;
 
-        {
-            let start = writer.write_len;
-
-            println!("Writing entries, {} entries", self.entries.len());
-            writer.write_u32(self.entries.len() as u32)?;
-            let mut prev_english_start = 0;
-            let mut prev_cost = 0;
-            for e in &self.entries
+        stats.measure("custom sources", writer, |w| {
+            w.write_u32(self.custom_sources.len() as u32)?;
+            for name in &self.custom_sources
             {
-                writer.write_u8(e.flags)?;
+                w.write_string(name)?;
+            }
 
-                assert!(e.characters.len() < 128);
-                writer.write_u8(e.characters.len() as u8)?;
-                for c in &e.characters
-                {
-                    writer.write_u16(*c)?;
-                }
+            Ok(())
+        })?;
+
+        let entry_codec = self.writing_entry_codec();
+        entry_codec.write(writer, &mut stats)?;
+
+        let core = &self.entries[..core_entries];
+        entry_codec.write_entries(writer, core, &mut stats)?;
+        self.english.write(writer, definition_range(core), true, &mut stats)?;
+
+        // Links into the tail are written with it
+        let links: Vec<EntryLink> = self.links.iter()
+            .filter(|x| (x.source_entry as usize) < core_entries && (x.target_entry as usize) < core_entries)
+            .copied()
+            .collect();
+        stats.link_count = links.len();
+        stats.measure("links", writer, |w| write_links(w, &links))?;
+
+        // Examples refer to every entry so can't be split
+        let examples = self.examples.as_ref().filter(|_| core_entries == self.entries.len());
+        stats.measure("examples", writer, |w| {
+            if let Some(examples) = examples {
+                w.write_u8(1)?;
+                examples.serialize(w)
+            }
+            else {
+                w.write_u8(0)
+            }
+        })?;
 
-                assert!(e.jyutping.len() < 256);
-                writer.write_u8(e.jyutping.len() as u8)?;
-                for j in &e.jyutping {
-                    writer.write_u16(j.pack())?;
-                }
+        // End padding
+        stats.measure("padding", writer, |w| w.write_u64(0))?;
 
-                assert!(prev_english_start <= e.english_start);
-                prev_english_start = e.english_start;
-                writer.write_u8((e.english_end - e.english_start) as u8)?;
+        stats.total_bytes = writer.write_len - start;
+        Ok(stats)
+    }
 
-                let cost_delta = e.cost - prev_cost;
-                writer.write_vbyte(cost_delta as u64)?;
-                prev_cost = e.cost;
-            }
+    /// Entries in range for a dictionary that already has the entries before it, see append_chunk
+    pub fn serialize_chunk<T : std::io::Write>(&self, writer : &mut DataWriter<T>, range : std::ops::Range<usize>) -> std::io::Result<DictionaryStats>
+    {
+        let start = writer.write_len;
+        let mut stats = DictionaryStats::from_entries(self, range.clone());
+
+        stats.measure("header", writer, |w| {
+            w.write_bytes(CHUNK_HEADER)?;
+            w.write_u32(CURRENT_VERSION)?;
+            w.write_u64(self.input_hash)?;
+            w.write_u32(range.start as u32)
+        })?;
+
+        let chunk = &self.entries[range.clone()];
+        self.writing_entry_codec().write_entries(writer, chunk, &mut stats)?;
+        self.english.write(writer, definition_range(chunk), false, &mut stats)?;
+
+        // A link is written with whichever of its entries comes last
+        let links: Vec<EntryLink> = self.links.iter()
+            .filter(|x| range.contains(&(x.source_entry.max(x.target_entry) as usize)))
+            .copied()
+            .collect();
+        stats.link_count = links.len();
+        stats.measure("links", writer, |w| write_links(w, &links))?;
+
+        // End padding
+        stats.measure("padding", writer, |w| w.write_u64(0))?;
 
-            let bytes = writer.write_len - start;
-            println!("Entries bytes = {}", bytes);
+        stats.total_bytes = writer.write_len - start;
+        Ok(stats)
+    }
+
+    /// Add entries written by serialize_chunk to a loaded dictionary, they are searchable straight away.
+    /// Chunks have to be appended in order, before any examples are attached.
+    /// A chunk that doesn't belong after the loaded entries is rejected and the dictionary left as it was.
+    pub fn append_chunk(&mut self, reader : &mut DataReader) -> std::result::Result<(), ChunkError> {
+        // Header, version, input hash and first entry
+        if (reader.data.len().saturating_sub(reader.position) < CHUNK_HEADER.len() + 16) {
+            return Err(ChunkError::BadHeader);
         }
 
-        writer.write_bytes(ENGLISH_BLOB_HEADER)?;
+        let header = reader.read_bytes_len(8);
+        if (header != CHUNK_HEADER) {
+            return Err(ChunkError::BadHeader);
+        }
 
-        {
-            let start = writer.write_len;
+        let version = reader.read_u32();
+        if (version != CURRENT_VERSION) {
+            return Err(ChunkError::Version(version));
+        }
 
-            println!("Writing english data, length = {}", self.english_data.len());
-            writer.write_bytes_and_length(&self.english_data)?;
+        let input_hash = reader.read_u64();
+        if (input_hash != self.input_hash) {
+            return Err(ChunkError::DifferentBuild);
+        }
 
-            let bytes = writer.write_len - start;
-            println!("English data bytes = {}", bytes);
+        let first_entry = reader.read_u32() as usize;
+        if (first_entry != self.entries.len()) {
+            return Err(ChunkError::NotContinuing { expected: self.entries.len(), first_entry });
         }
 
-        {
-            let start = writer.write_len;
+        if (self.examples.is_some()) {
+            return Err(ChunkError::ExamplesAttached);
+        }
 
-            println!("Writing english data starts, length = {}", self.english_data_starts.len());
-            let mut prev_start = 0;
-            writer.write_u32(self.english_data_starts.len() as u32)?;
-            for start in &self.english_data_starts
-            {
-                let delta = *start - prev_start;
-                prev_start = *start;
-                writer.write_vbyte(delta as u64)?;
+        let entries = self.entry_codec.read_entries(reader, self.english.definition_count() as u32);
+        if let (Some(last), Some(first)) = (self.entries.last(), entries.first()) {
+            // Search relies on entries being sorted by cost
+            if (last.cost > first.cost) {
+                return Err(ChunkError::NotInCostOrder);
             }
-
-            let bytes = writer.write_len - start;
-            println!("English starts bytes = {}", bytes);
         }
 
-        // End padding
-        writer.write_u64(0)?;
+        self.english.append(reader);
+
+        debug_log!("Appended {} entries after {}", entries.len(), first_entry);
+        self.entries.extend(entries);
+
+        self.links.extend(read_links(reader));
+        self.links.sort_by_key(|x| x.source_entry);
 
         Ok(())
     }
 
+    // Built from every entry so the core and its chunks agree
+    fn build_entry_codec(&self) -> EntryCodec {
+        EntryCodec::from_entries(&self.entries, self.character_store.characters.len(), self.jyutping_store.base_strings.len())
+    }
+
+    // A loaded dictionary keeps the codec it was written with, a core's covers the chunks it hasn't loaded
+    fn writing_entry_codec(&self) -> EntryCodec {
+        if (self.entry_codec.characters.is_empty() && self.entry_codec.syllables.is_empty()) {
+            self.build_entry_codec()
+        }
+        else {
+            self.entry_codec.clone()
+        }
+    }
+
     pub fn get_display_entry(&self, i: usize) -> DisplayDictionaryEntry {
         DisplayDictionaryEntry::from_entry(&self.entries[i], self)
     }
@@ -328,6 +552,148 @@ impl CompiledDictionary {
     pub fn get_diplay_entry(&self, i: usize) -> DisplayDictionaryEntry {
         self.get_display_entry(i)
     }
+
+    /// Entries with the same reading as a space separated jyutping string, ordered by cost.
+    /// Syllables written without a tone match any tone.
+    pub fn homophones(&self, jyutping: &str, tone_sensitive: bool) -> Vec<usize> {
+        let mut reading : Vec<(u16, Option<u8>)> = Vec::new();
+        for syllable in jyutping.split_whitespace() {
+            let syllable = syllable.to_ascii_lowercase();
+            let (base, tone) = parse_jyutping_tone(&syllable);
+            let Ok(base) = self.jyutping_store.base_strings.binary_search_by(|x| x[..].cmp(base)) else {
+                // Not a syllable in the dictionary so nothing can match
+                return Vec::new();
+            };
+
+            reading.push((base as u16, if (tone_sensitive) { tone } else { None }));
+        }
+
+        if (reading.is_empty()) {
+            return Vec::new();
+        }
+
+        // Entries are already sorted by cost
+        let mut found = Vec::new();
+        for (entry_id, entry) in self.entries.iter().enumerate() {
+            if (entry.jyutping.len() != reading.len()) {
+                continue;
+            }
+
+            let same_reading = entry.jyutping.iter().zip(&reading)
+                .all(|(j, (base, tone))| j.base == *base && tone.is_none_or(|x| x == j.tone));
+
+            if (same_reading) {
+                found.push(entry_id);
+            }
+        }
+
+        found
+    }
+
+    /// Other entries read the same as the given entry
+    pub fn entry_homophones(&self, entry_id: usize, tone_sensitive: bool) -> Vec<usize> {
+        let entry = &self.entries[entry_id];
+        let jyutping : Vec<String> = entry.jyutping.iter().map(|j| self.jyutping_store.get_string(*j)).collect();
+
+        let mut found = self.homophones(&jyutping.join(" "), tone_sensitive);
+        found.retain(|x| *x != entry_id);
+        found
+    }
+
+    pub fn get_character_info(&self, c: char) -> Option<&CharacterInfo> {
+        self.character_store.get_info(c)
+    }
+
+    /// Characters in the dictionary matching a radical / stroke count query
+    pub fn find_characters(&self, query: &CharacterQuery) -> Vec<char> {
+        self.character_store.find(query)
+    }
+
+    /// Empty unless example sentences have been loaded
+    pub fn examples_for(&self, entry_id: usize, limit: usize) -> Vec<&ExampleSentence> {
+        match &self.examples {
+            Some(examples) => examples.examples_for(entry_id, limit),
+            None => Vec::new(),
+        }
+    }
+
+    /// Attach examples shipped separately from the dictionary, they must come from the same build.
+    /// A core whose chunks failed to load is a prefix of that build so can still use them.
+    pub fn set_examples(&mut self, examples: ExampleStore) {
+        assert!(self.examples_fit(&examples), "Examples built for a different dictionary");
+        self.examples = Some(examples);
+    }
+
+    pub fn examples_fit(&self, examples: &ExampleStore) -> bool {
+        examples.input_hash == self.input_hash && examples.entry_count() >= self.entries.len()
+    }
+
+    pub fn get_links(&self, entry_id: usize) -> &[EntryLink] {
+        let start = self.links.partition_point(|x| (x.source_entry as usize) < entry_id);
+        let end = self.links.partition_point(|x| (x.source_entry as usize) <= entry_id);
+        &self.links[start..end]
+    }
+
+    /// Source for a single FLAG_SOURCE_* bit, custom sources need the name table
+    pub fn get_source(&self, flag: u8, custom_source: u8) -> EntrySource {
+        if (flag & FLAG_SOURCE_CUSTOM != 0) {
+            EntrySource::Custom(self.custom_sources[custom_source as usize].clone())
+        }
+        else {
+            EntrySource::from_flags(flag)
+        }
+    }
+}
+
+// The definitions of a run of consecutive entries
+fn definition_range(entries : &[CompiledDictionaryEntry]) -> std::ops::Range<u32>
+{
+    match (entries.first(), entries.last()) {
+        (Some(first), Some(last)) => first.english_start..last.english_end,
+        _ => 0..0,
+    }
+}
+
+fn write_links<T : std::io::Write>(writer : &mut DataWriter<T>, links : &[EntryLink]) -> std::io::Result<()>
+{
+    writer.write_bytes(LINKS_HEADER)?;
+
+    writer.write_u32(links.len() as u32)?;
+    let mut prev_source = 0;
+    for link in links
+    {
+        assert!(prev_source <= link.source_entry);
+        writer.write_vbyte((link.source_entry - prev_source) as u64)?;
+        prev_source = link.source_entry;
+
+        writer.write_vbyte(link.target_entry as u64)?;
+        writer.write_u8(link.kind as u8)?;
+    }
+
+    Ok(())
+}
+
+fn read_links(reader : &mut DataReader) -> Vec<EntryLink>
+{
+    let links_header = reader.read_bytes_len(8);
+    assert!(links_header == LINKS_HEADER);
+
+    let links_count = reader.read_u32() as usize;
+    let mut links = Vec::with_capacity(links_count);
+    let mut prev_source = 0;
+    for _ in 0..links_count
+    {
+        let source_entry = prev_source + reader.read_vbyte() as u32;
+        prev_source = source_entry;
+
+        links.push(EntryLink {
+            source_entry,
+            target_entry: reader.read_vbyte() as u32,
+            kind: CrossReferenceKind::from_u8(reader.read_u8()),
+        });
+    }
+
+    links
 }
 
 impl CharacterStore
@@ -338,18 +704,49 @@ impl CharacterStore
 
         Self {
             characters,
+            info: Vec::new(),
         }
     }
+
+    /// Characters missing from the table get an empty CharacterInfo
+    pub fn set_info(&mut self, table : &CharacterInfoTable)
+    {
+        self.info = self.characters.iter().map(|c| table.get(*c).cloned().unwrap_or_default()).collect();
+        let found = self.info.iter().filter(|x| !x.is_empty()).count();
+        debug_log!("Character info for {} of {} characters", found, self.characters.len());
+    }
+
     pub fn char_to_index(&self, c : char) -> Option<u16>
     {
         self.characters.binary_search(&c).map(|x| x as u16).ok()
     }
+
+    pub fn get_info(&self, c : char) -> Option<&CharacterInfo>
+    {
+        let index = self.char_to_index(c)? as usize;
+        self.info.get(index).filter(|x| !x.is_empty())
+    }
+
+    /// Ordered by radical then additional strokes, for radical lookup tables
+    pub fn find(&self, query : &CharacterQuery) -> Vec<char>
+    {
+        let mut found : Vec<(u8, u8, char)> = self.characters.iter().zip(&self.info)
+            .filter(|(_, info)| query.matches(info))
+            .map(|(c, info)| (info.radical, info.additional_strokes, *c))
+            .collect();
+
+        found.sort();
+        found.into_iter().map(|(_, _, c)| c).collect()
+    }
 }
 
 #[derive(Default, Debug)]
 pub struct CharacterStore
 {
     pub characters : Vec<char>,
+
+    // Either empty or parallel to characters
+    pub info : Vec<CharacterInfo>,
 }
 
 #[derive(Default, Debug)]
@@ -403,7 +800,8 @@ impl JyutpingStore {
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Jyutping
 {
-    // @Perf merge to single u16, don't need all the bits for base
+    // @Perf merge to single u16, don't need all the bits for base.
+    // Files already use a combined syllable id, see EntryCodec
     pub base : u16,
     pub tone : u8,
 }
@@ -444,14 +842,90 @@ pub struct CompiledDictionaryEntry
     pub english_end : u32,
     pub cost : u32,
     pub flags: u8,
+
+    // Index into CompiledDictionary::custom_sources for single source custom entries
+    pub custom_source: u8,
+
+    // Only filled in for entries merged from multiple sources
+    pub source_groups: Vec<SourceGroup>,
+
+    // Boxed as most entries have none
+    pub annotations: Option<Box<EntryAnnotations>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SourceGroup
+{
+    // FLAG_SOURCE_*
+    pub source: u8,
+    // Only meaningful when source is FLAG_SOURCE_CUSTOM
+    pub custom_source: u8,
+    pub definition_count: u8,
 }
 
 impl CompiledDictionaryEntry
 {
-    pub fn get_source(&self) -> EntrySource {
-        if self.flags & FLAG_SOURCE_CEDICT != 0 {
+    pub fn get_annotations(&self) -> EntryAnnotations {
+        self.annotations.as_ref().map(|x| x.as_ref().clone()).unwrap_or_default()
+    }
+
+    pub fn get_sources(&self, dict: &CompiledDictionary) -> Vec<EntrySource> {
+        self.get_source_groups(dict).into_iter().map(|(source, _, _)| source).collect()
+    }
+
+    /// Each source with its range of definitions, as indexes into the english definitions
+    pub fn get_source_groups(&self, dict: &CompiledDictionary) -> Vec<(EntrySource, u32, u32)> {
+        if self.source_groups.is_empty() {
+            return vec![(dict.get_source(self.flags, self.custom_source), self.english_start, self.english_end)];
+        }
+
+        let mut groups = Vec::with_capacity(self.source_groups.len());
+        let mut start = self.english_start;
+        for group in &self.source_groups {
+            let end = start + group.definition_count as u32;
+            groups.push((dict.get_source(group.source, group.custom_source), start, end));
+            start = end;
+        }
+
+        groups
+    }
+}
+
+pub const FLAG_SOURCE_CEDICT: u8 = 0x1;
+pub const FLAG_SOURCE_CCCANTO: u8 = 0x2;
+pub const FLAG_SOURCE_CUSTOM: u8 = 0x4;
+
+// Entry has classifiers, labels, cross references or comments
+pub const FLAG_HAS_ANNOTATIONS: u8 = 0x40;
+// Entry has explicit source_groups, set when more than one source was merged
+pub const FLAG_MERGED_SOURCES: u8 = 0x80;
+
+impl EntrySource {
+    pub fn flag(&self) -> u8 {
+        match self {
+            EntrySource::CEDict => FLAG_SOURCE_CEDICT,
+            EntrySource::CCanto => FLAG_SOURCE_CCCANTO,
+            EntrySource::Custom(_) => FLAG_SOURCE_CUSTOM,
+        }
+    }
+
+    pub fn is_custom(&self) -> bool {
+        matches!(self, EntrySource::Custom(_))
+    }
+
+    pub fn display_name(&self) -> &str {
+        match self {
+            EntrySource::CEDict => "CEDict",
+            EntrySource::CCanto => "CC-Canto",
+            EntrySource::Custom(name) => name,
+        }
+    }
+
+    // Lowest set source bit wins, custom sources need CompiledDictionary::get_source
+    pub fn from_flags(flags: u8) -> Self {
+        if flags & FLAG_SOURCE_CEDICT != 0 {
             EntrySource::CEDict
-        } else if self.flags & FLAG_SOURCE_CCCANTO != 0 {
+        } else if flags & FLAG_SOURCE_CCCANTO != 0 {
             EntrySource::CCanto
         } else {
             panic!("Unknown data source");
@@ -459,9 +933,6 @@ impl CompiledDictionaryEntry
     }
 }
 
-pub const FLAG_SOURCE_CEDICT: u8 = 0x1;
-pub const FLAG_SOURCE_CCCANTO: u8 = 0x2;
-
 pub struct Result
 {
     entry_index : usize,
@@ -471,13 +942,15 @@ pub struct Result
 
 
 #[derive(Debug, Serialize)]
+#[cfg_attr(feature = "tsify", derive(tsify::Tsify))]
 pub struct DisplayDictionaryEntry
 {
     pub characters : String,
     pub jyutping : String,
     pub english_definitions : Vec<String>,
     pub cost : u32,
-    pub entry_source: EntrySource,
+    pub entry_sources: Vec<EntrySource>,
+    pub annotations : EntryAnnotations,
 }
 
 impl DisplayDictionaryEntry
@@ -503,21 +976,18 @@ impl DisplayDictionaryEntry
         let mut english_definitions = Vec::with_capacity(entry.english_end as usize - entry.english_start as usize);
         for i in entry.english_start..entry.english_end
         {
-            let start = dict.english_data_starts[i as usize] as usize;
-            let end = dict.english_data_starts[i as usize + 1] as usize;
-            let blob = &dict.english_data[start..end];
-            let def = unsafe { std::str::from_utf8_unchecked(blob) }.to_owned();
-            english_definitions.push(def);
+            english_definitions.push(dict.english.definition(i));
         }
 
-        let entry_source = entry.get_source();
+        let entry_sources = entry.get_sources(dict);
 
         Self {
             characters,
             jyutping,
             english_definitions,
             cost : entry.cost,
-            entry_source,
+            entry_sources,
+            annotations : entry.get_annotations(),
         }
     }
 }
@@ -525,7 +995,7 @@ impl DisplayDictionaryEntry
 #[cfg(test)]
 pub mod tests {
     use crate::Stopwatch;
-    use crate::search::{JYUTPING_COMPLETION_PENALTY_K, JYUTPING_PARTIAL_MATCH_PENALTY_K, JyutpingQueryTerm, MatchType, QueryTerms};
+    use crate::search::{JYUTPING_COMPLETION_PENALTY_K, JYUTPING_PARTIAL_MATCH_PENALTY_K, JyutpingQueryTerm, MatchType, QueryTerms, RankingConfig};
 
     use super::*;
 
@@ -566,6 +1036,7 @@ pub mod tests {
         // Characters must be in sorted order!
         let character_store = CharacterStore {
             characters: vec!['學', '師', '生', '老'], // sorted order
+            info: Vec::new(),
         };
 
         let jyutping_store = JyutpingStore {
@@ -588,6 +1059,7 @@ pub mod tests {
                 english_end: 1,
                 cost: 100,
                 flags: FLAG_SOURCE_CEDICT,
+                ..Default::default()
             },
             CompiledDictionaryEntry {
                 characters: vec![0, 2], // 學生 (學=index 0, 生=index 2)
@@ -599,19 +1071,309 @@ pub mod tests {
                 english_end: 2,
                 cost: 100,
                 flags: FLAG_SOURCE_CEDICT,
+                ..Default::default()
             },
         ];
 
-        let english_data = b"teacherstudent".to_vec();
-        let english_data_starts = vec![0, 7, 14];
-
         CompiledDictionary {
             character_store,
             jyutping_store,
             entries,
-            english_data,
-            english_data_starts,
+            english: EnglishStore::from_definitions(&["teacher", "student"]),
+            ..Default::default()
+        }
+    }
+
+    pub fn roundtrip(dict: &CompiledDictionary) -> CompiledDictionary {
+        let mut writer = DataWriter {
+            write_len: 0,
+            inner: BufWriter::new(Vec::new()),
+        };
+
+        dict.serialize(&mut writer).unwrap();
+        let buffer = writer.inner.into_inner().unwrap();
+        CompiledDictionary::deserialize(&mut DataReader::new(&buffer))
+    }
+
+    #[test]
+    fn test_serialize_roundtrip_merged_sources() {
+        let mut dict = create_test_dict();
+        dict.entries[0].flags = FLAG_SOURCE_CEDICT | FLAG_SOURCE_CCCANTO | FLAG_MERGED_SOURCES;
+        dict.entries[0].source_groups = vec![
+            SourceGroup { source: FLAG_SOURCE_CCCANTO, custom_source: 0, definition_count: 1 },
+            SourceGroup { source: FLAG_SOURCE_CEDICT, custom_source: 0, definition_count: 0 },
+        ];
+
+        let read = roundtrip(&dict);
+        assert_eq!(dict.entries.len(), read.entries.len());
+        assert_eq!(dict.entries[0].source_groups, read.entries[0].source_groups);
+        assert_eq!(vec![EntrySource::CCanto, EntrySource::CEDict], read.entries[0].get_sources(&read));
+        assert_eq!(vec![EntrySource::CEDict], read.entries[1].get_sources(&read));
+        assert_eq!(dict.english.data, read.english.data);
+        assert_eq!(dict.english.definition_starts, read.english.definition_starts);
+    }
+
+    #[test]
+    fn test_serialize_roundtrip_annotations() {
+        use crate::annotations::*;
+
+        let mut dict = create_test_dict();
+        let annotations = EntryAnnotations {
+            classifiers: vec![Classifier { characters: "個".to_owned(), jyutping: "go3".to_owned() }],
+            labels: vec![DefinitionLabel { definition: 0, label: "spoken".to_owned() }],
+            cross_references: vec![CrossReference { kind: CrossReferenceKind::SeeAlso, characters: "先生".to_owned(), jyutping: String::new() }],
+            comments: vec!["adapted from cc-cedict".to_owned()],
+        };
+
+        dict.entries[1].flags |= FLAG_HAS_ANNOTATIONS;
+        dict.entries[1].annotations = Some(Box::new(annotations.clone()));
+
+        let read = roundtrip(&dict);
+        assert!(read.entries[0].annotations.is_none());
+        assert_eq!(Some(Box::new(annotations.clone())), read.entries[1].annotations);
+        assert_eq!(annotations, read.get_display_entry(1).annotations);
+        assert_eq!(vec!["student"], read.get_display_entry(1).english_definitions);
+    }
+
+    #[test]
+    fn test_from_builder_resolves_links() {
+        use crate::annotations::CrossReferenceKind;
+        use crate::builder::DictionaryEntry;
+        use crate::StringVecSet;
+
+        let mut builder = Builder::default();
+        builder.entries.push(DictionaryEntry::new("一並", "jat1 bing6", EntrySource::CEDict, StringVecSet::single("variant of 一併|一并[yi1 bing4]".to_owned()), 3_000));
+        builder.entries.push(DictionaryEntry::new("一併", "jat1 bing6", EntrySource::CEDict, StringVecSet::single("to lump together".to_owned()), 1_000));
+        builder.entries.push(DictionaryEntry::new("一齊", "jat1 cai4", EntrySource::CEDict, StringVecSet::single("see also 一起|一起[yi1 qi3]".to_owned()), 2_000));
+        builder.extract_annotations();
+
+        let dict = roundtrip(&CompiledDictionary::from_builder(builder));
+
+        // Sorted by cost, 一併 is entry 0
+        assert_eq!(vec![EntryLink { source_entry: 2, target_entry: 0, kind: CrossReferenceKind::VariantOf }], dict.links);
+        assert_eq!(dict.links.as_slice(), dict.get_links(2));
+        assert!(dict.get_links(0).is_empty());
+
+        // The variant definition is only a reference, so it has nothing else left
+        assert!(dict.get_display_entry(2).english_definitions.is_empty());
+        assert!(dict.get_display_entry(2).annotations.cross_references.is_empty());
+
+        // No entry for 一起 so it stays as text
+        assert_eq!("一起", dict.get_display_entry(1).annotations.cross_references[0].characters);
+    }
+
+    #[test]
+    fn test_core_and_chunks() {
+        use crate::builder::DictionaryEntry;
+        use crate::StringVecSet;
+
+        let mut builder = Builder::default();
+        for (traditional, jyutping, english, cost) in [
+            ("路", "lou6", "road", 500),
+            ("老", "lou5", "old", 600),
+            ("佬", "lou2", "variant of 師", 700),
+            ("學", "hok6", "to learn/see also 老", 800),
+            ("師", "si1", "teacher/master", 900),
+            ("老師", "lou5 si1", "teacher/variant of 路", 1_000),
+        ] {
+            let definitions = StringVecSet { inner: english.split('/').map(|x| x.to_owned()).collect() };
+            builder.entries.push(DictionaryEntry::new(traditional, jyutping, EntrySource::CEDict, definitions, cost));
         }
+        builder.extract_annotations();
+
+        let built = CompiledDictionary::from_builder(builder);
+        assert_eq!(3, built.links.len());
+
+        let write = |f: &dyn Fn(&mut DataWriter<Vec<u8>>)| -> Vec<u8> {
+            let mut writer = DataWriter {
+                write_len: 0,
+                inner: BufWriter::new(Vec::new()),
+            };
+
+            f(&mut writer);
+            writer.inner.into_inner().unwrap()
+        };
+
+        let core = write(&|w| { built.serialize_core(w, 3).unwrap(); });
+        let chunks = [
+            write(&|w| { built.serialize_chunk(w, 3..5).unwrap(); }),
+            write(&|w| { built.serialize_chunk(w, 5..6).unwrap(); }),
+        ];
+
+        // The core is a dictionary by itself, without links to entries it doesn't have
+        let mut dict = CompiledDictionary::deserialize(&mut DataReader::new(&core));
+        assert_eq!(3, dict.entries.len());
+        assert!(dict.links.is_empty());
+        assert_eq!(3, dict.search("lou", 8, Box::new(TestStopwatch)).matches.len());
+
+        dict.append_chunk(&mut DataReader::new(&chunks[0])).unwrap();
+        assert_eq!(5, dict.entries.len());
+        assert_eq!(2, dict.links.len());
+        assert_eq!(4, dict.get_links(2)[0].target_entry);
+        assert_eq!(vec!["teacher", "master"], dict.get_display_entry(4).english_definitions);
+
+        dict.append_chunk(&mut DataReader::new(&chunks[1])).unwrap();
+
+        let full = roundtrip(&built);
+        assert_eq!(full.links, dict.links);
+        assert_eq!(full.english.tokens, dict.english.tokens);
+        assert_eq!(full.english.data, dict.english.data);
+        assert_eq!(full.english.definition_starts, dict.english.definition_starts);
+        for i in 0..full.entries.len() {
+            assert_eq!(format!("{:?}", full.get_display_entry(i)), format!("{:?}", dict.get_display_entry(i)));
+        }
+
+        let ids = |dict: &CompiledDictionary| -> Vec<usize> {
+            dict.search("lou", 8, Box::new(TestStopwatch)).matches.iter().map(|x| x.match_obj.entry_id).collect()
+        };
+        assert_eq!(ids(&full), ids(&dict));
+
+        // A chunk only fits the build it came from, after the entries before it
+        let mut other = CompiledDictionary::deserialize(&mut DataReader::new(&core));
+        other.input_hash += 1;
+        assert_eq!(Err(ChunkError::DifferentBuild), other.append_chunk(&mut DataReader::new(&chunks[0])));
+        assert_eq!(3, other.entries.len());
+
+        let mut other = CompiledDictionary::deserialize(&mut DataReader::new(&core));
+        assert_eq!(Err(ChunkError::NotContinuing { expected: 3, first_entry: 5 }), other.append_chunk(&mut DataReader::new(&chunks[1])));
+        assert_eq!(Err(ChunkError::BadHeader), other.append_chunk(&mut DataReader::new(&core)));
+        assert_eq!(Err(ChunkError::BadHeader), other.append_chunk(&mut DataReader::new(&chunks[0][..10])));
+        assert_eq!(3, other.entries.len());
+    }
+
+    #[test]
+    fn test_examples_fit_build() {
+        let dict = create_test_dict();
+        let examples = |input_hash: u64, entry_count: usize| -> ExampleStore {
+            ExampleStore { input_hash, entry_starts: vec![0; entry_count + 1], ..Default::default() }
+        };
+
+        assert!(dict.examples_fit(&examples(dict.input_hash, 2)));
+        // A core is a prefix of the build its examples came from
+        assert!(dict.examples_fit(&examples(dict.input_hash, 3)));
+        assert!(!dict.examples_fit(&examples(dict.input_hash, 1)));
+        assert!(!dict.examples_fit(&examples(dict.input_hash + 1, 2)));
+    }
+
+    #[test]
+    fn test_build_independent_of_input_order() {
+        use crate::builder::DictionaryEntry;
+        use crate::StringVecSet;
+
+        let inputs = [
+            ("老師", "lou5 si1", EntrySource::CEDict, "teacher", 1_000),
+            ("老師", "Lou5 si1", EntrySource::CCanto, "sir", 1_000),
+            ("老獅", "lou5 si1", EntrySource::CEDict, "old lion", 1_000),
+            ("路", "lou6", EntrySource::CCanto, "road", 500),
+            ("佬", "lou2", EntrySource::CEDict, "guy", 500),
+        ];
+
+        let build = |order: &mut dyn Iterator<Item = usize>| -> Vec<u8> {
+            let mut builder = Builder::default();
+            for i in order {
+                let (traditional, jyutping, source, english, cost) = &inputs[i];
+                builder.entries.push(DictionaryEntry::new(traditional, jyutping, source.clone(), StringVecSet::single(english.to_string()), *cost));
+            }
+            builder.merge_duplicates();
+
+            let mut writer = DataWriter {
+                write_len: 0,
+                inner: BufWriter::new(Vec::new()),
+            };
+            CompiledDictionary::from_builder(builder).serialize(&mut writer).unwrap();
+            writer.inner.into_inner().unwrap()
+        };
+
+        let forward = build(&mut (0..inputs.len()));
+        assert_eq!(forward, build(&mut (0..inputs.len()).rev()));
+
+        let dict = CompiledDictionary::deserialize(&mut DataReader::new(&forward));
+        assert_eq!(4, dict.entries.len());
+        assert_eq!("佬", dict.get_display_entry(0).characters);
+        assert_eq!(vec![EntrySource::CEDict, EntrySource::CCanto], dict.get_display_entry(2).entry_sources);
+        assert_eq!("lou5 si1", dict.get_display_entry(2).jyutping);
+    }
+
+    #[test]
+    fn test_homophones() {
+        use crate::builder::DictionaryEntry;
+        use crate::StringVecSet;
+
+        let mut builder = Builder::default();
+        builder.entries.push(DictionaryEntry::new("老師", "lou5 si1", EntrySource::CEDict, StringVecSet::single("teacher".to_owned()), 1_000));
+        builder.entries.push(DictionaryEntry::new("螺螄", "lo4 si1", EntrySource::CEDict, StringVecSet::single("river snail".to_owned()), 4_000));
+        builder.entries.push(DictionaryEntry::new("老是", "lou5 si6", EntrySource::CEDict, StringVecSet::single("always".to_owned()), 3_000));
+        builder.entries.push(DictionaryEntry::new("老獅", "lou5 si1", EntrySource::CEDict, StringVecSet::single("old lion".to_owned()), 2_000));
+        builder.entries.push(DictionaryEntry::new("路", "lou6", EntrySource::CEDict, StringVecSet::single("road".to_owned()), 500));
+        builder.entries.push(DictionaryEntry::new("老", "lou5", EntrySource::CEDict, StringVecSet::single("old".to_owned()), 600));
+
+        let dict = CompiledDictionary::from_builder(builder);
+        let characters = |ids: Vec<usize>| -> Vec<String> {
+            ids.into_iter().map(|x| dict.get_display_entry(x).characters).collect()
+        };
+
+        assert_eq!(vec!["老師", "老獅"], characters(dict.homophones("lou5 si1", true)));
+        assert_eq!(vec!["老師", "老獅", "老是"], characters(dict.homophones("lou5 si1", false)));
+        assert_eq!(vec!["老師", "老獅", "老是"], characters(dict.homophones("Lou5 si", true)));
+        assert_eq!(vec!["路", "老"], characters(dict.homophones("lou", true)));
+        assert!(dict.homophones("xyz1", false).is_empty());
+        assert!(dict.homophones("", false).is_empty());
+
+        let teacher = dict.homophones("lou5 si1", true)[0];
+        assert_eq!(vec!["老獅"], characters(dict.entry_homophones(teacher, true)));
+        assert_eq!(vec!["老獅", "老是"], characters(dict.entry_homophones(teacher, false)));
+    }
+
+    #[test]
+    fn test_from_builder_character_info() {
+        use crate::builder::DictionaryEntry;
+        use crate::StringVecSet;
+
+        let mut builder = Builder::default();
+        builder.entries.push(DictionaryEntry::new("老虎", "lou5 fu2", EntrySource::CEDict, StringVecSet::single("tiger".to_owned()), 1_000));
+        builder.entries.push(DictionaryEntry::new("老師", "lou5 si1", EntrySource::CEDict, StringVecSet::single("teacher".to_owned()), 2_000));
+        builder.character_info = CharacterInfoTable::parse_str(concat!(
+            "U+864E\tkRSUnicode\t141.2\n",
+            "U+864E\tkTotalStrokes\t8\n",
+            "U+864E\tkCantonese\tfu2\n",
+            "U+8001\tkRSUnicode\t125.0\n",
+            "U+8001\tkTotalStrokes\t6\n",
+            "U+5E2B\tkRSUnicode\t50.7\n",
+            "U+5E2B\tkTotalStrokes\t10\n",
+            // Not in any entry
+            "U+7345\tkRSUnicode\t94.10\n",
+        ));
+
+        let dict = roundtrip(&CompiledDictionary::from_builder(builder));
+
+        assert_eq!(vec!["fu2".to_owned()], dict.get_character_info('虎').unwrap().readings);
+        assert!(dict.get_character_info('獅').is_none());
+
+        assert_eq!(vec!['師', '老', '虎'], dict.find_characters(&CharacterQuery::default()));
+        assert_eq!(vec!['虎'], dict.find_characters(&CharacterQuery { radical: Some(141), ..Default::default() }));
+        assert_eq!(vec!['老'], dict.find_characters(&CharacterQuery { total_strokes: Some(6), ..Default::default() }));
+        assert!(dict.find_characters(&CharacterQuery { radical: Some(94), ..Default::default() }).is_empty());
+
+        // Dictionaries built without character info have none
+        assert!(create_test_dict().get_character_info('老').is_none());
+    }
+
+    #[test]
+    fn test_serialize_roundtrip_custom_sources() {
+        let mut dict = create_test_dict();
+        dict.custom_sources = vec!["legal".to_owned(), "medical".to_owned()];
+        dict.entries[0].flags = FLAG_SOURCE_CEDICT | FLAG_SOURCE_CUSTOM | FLAG_MERGED_SOURCES;
+        dict.entries[0].source_groups = vec![
+            SourceGroup { source: FLAG_SOURCE_CEDICT, custom_source: 0, definition_count: 0 },
+            SourceGroup { source: FLAG_SOURCE_CUSTOM, custom_source: 1, definition_count: 1 },
+        ];
+        dict.entries[1].flags = FLAG_SOURCE_CUSTOM;
+        dict.entries[1].custom_source = 0;
+
+        let read = roundtrip(&dict);
+        assert_eq!(dict.custom_sources, read.custom_sources);
+        assert_eq!(vec![EntrySource::CEDict, EntrySource::Custom("medical".to_owned())], read.entries[0].get_sources(&read));
+        assert_eq!(vec![EntrySource::Custom("legal".to_owned())], read.entries[1].get_sources(&read));
     }
 
     #[test]
@@ -1167,6 +1929,7 @@ pub mod tests {
         // Characters must be in sorted order!
         let character_store = CharacterStore {
             characters: vec!['學', '師', '生', '老'], // sorted order
+            info: Vec::new(),
         };
 
         let jyutping_store = JyutpingStore {
@@ -1187,18 +1950,16 @@ pub mod tests {
                 english_end: 1,
                 cost: 0,
                 flags: FLAG_SOURCE_CEDICT,
+                ..Default::default()
             }
         ];
 
-        let english_data = b"father".to_vec();
-        let english_data_starts = vec![0, 6];
-
         let dict = CompiledDictionary {
             character_store,
             jyutping_store,
             entries,
-            english_data,
-            english_data_starts,
+            english: EnglishStore::from_definitions(&["father"]),
+            ..Default::default()
         };
 
         // Should be exact match
@@ -1223,4 +1984,226 @@ pub mod tests {
         assert_eq!(1, res.matches[0].matched_spans.len());
         assert_eq!((0, 8), res.matches[0].matched_spans[0]);
     }
+
+    #[test]
+    fn test_ranking_config_partial_json() {
+        let config = RankingConfig::from_json(r#"{ "english_base_penalty": 0 }"#).unwrap();
+        assert_eq!(0, config.english_base_penalty);
+        assert_eq!(JYUTPING_COMPLETION_PENALTY_K, config.jyutping_completion_penalty_k);
+
+        let roundtrip = RankingConfig::from_json(&config.to_json()).unwrap();
+        assert_eq!(config, roundtrip);
+
+        assert!(RankingConfig::from_json("{ not json").is_err());
+    }
+
+    #[test]
+    fn test_search_with_config() {
+        let dict = create_test_dict();
+
+        // "saa" is two characters short of "saang"
+        let config = RankingConfig {
+            jyutping_completion_penalty_k: 1_000,
+            ..Default::default()
+        };
+
+        let res = dict.search_with_config("saa", 8, &config, Box::new(TestStopwatch));
+        assert_eq!(1, res.matches[0].match_obj.entry_id);
+        assert_eq!(2 * 1_000, res.matches[0].match_obj.cost_info.term_match_cost);
+
+        let res = dict.search("saa", 8, Box::new(TestStopwatch));
+        assert_eq!(2 * JYUTPING_COMPLETION_PENALTY_K, res.matches[0].match_obj.cost_info.term_match_cost);
+    }
+
+    #[test]
+    fn test_search_pages() {
+        use crate::builder::DictionaryEntry;
+        use crate::search::SearchCursor;
+        use crate::StringVecSet;
+
+        let mut builder = Builder::default();
+        for (traditional, jyutping, english, cost) in [
+            ("老師", "lou5 si1", "teacher", 1_000),
+            ("老鼠", "lou5 syu2", "mouse", 1_000),
+            ("路", "lou6", "road", 500),
+            ("老", "lou5", "old", 600),
+            ("佬", "lou2", "guy; lou", 2_000),
+            ("學", "hok6", "to learn", 100_000),
+        ] {
+            builder.entries.push(DictionaryEntry::new(traditional, jyutping, EntrySource::CEDict, StringVecSet::single(english.to_owned()), cost));
+        }
+
+        let dict = CompiledDictionary::from_builder(builder);
+        let all = dict.search("lou", 8, Box::new(TestStopwatch));
+        assert_eq!(5, all.matches.len());
+        assert_eq!(5, all.internal_candidates);
+        assert!(all.exhaustive);
+        assert!(all.next_cursor.is_none());
+
+        let mut paged = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = dict.search_page("lou", 1, cursor.as_ref(), &RankingConfig::default(), &TestStopwatch);
+            // A page that stopped early only knows a lower bound
+            if (page.exhaustive) {
+                assert_eq!(all.internal_candidates, page.internal_candidates);
+            }
+            else {
+                assert!(page.internal_candidates <= all.internal_candidates);
+            }
+
+            assert!(!page.matches.is_empty());
+            paged.extend(page.matches.iter().map(|x| x.match_obj.entry_id));
+
+            match page.next_cursor {
+                Some(next) => cursor = SearchCursor::from_token(&next.to_token()),
+                None => break,
+            }
+        }
+
+        let all_ids: Vec<usize> = all.matches.iter().map(|x| x.match_obj.entry_id).collect();
+        assert_eq!(all_ids, paged);
+
+        // Stops scanning once nothing else can make the page
+        let first = dict.search("lou", 1, Box::new(TestStopwatch));
+        assert!(!first.exhaustive);
+        assert!(first.next_cursor.is_some());
+
+        // Skipped entries are checked for another match before giving a cursor
+        let last = dict.search("lou", 5, Box::new(TestStopwatch));
+        assert_eq!(5, last.matches.len());
+        assert_eq!(5, last.internal_candidates);
+        assert!(last.exhaustive);
+        assert!(last.next_cursor.is_none());
+
+        // A full page with nothing left to skip is the last
+        let exact = dict.search("lou5 s", 2, Box::new(TestStopwatch));
+        assert_eq!(2, exact.internal_candidates);
+        assert!(exact.exhaustive);
+        assert!(exact.next_cursor.is_none());
+
+        assert!(SearchCursor::from_token("1.2.3").is_none());
+        assert!(SearchCursor::from_token("1.2.3.4.5").is_none());
+        assert!(SearchCursor::from_token("a.2.3.4").is_none());
+    }
+
+    #[test]
+    fn test_top_k_matches_full_scan() {
+        use crate::builder::DictionaryEntry;
+        use crate::StringVecSet;
+
+        let mut builder = Builder::default();
+        for (traditional, jyutping, english, cost) in [
+            ("老師", "lou5 si1", "teacher", 1_000),
+            ("老鼠", "lou5 syu2", "mouse; rat", 1_200),
+            ("路", "lou6", "road; path", 500),
+            ("老", "lou5", "old; always", 600),
+            ("佬", "lou2", "guy; fellow", 2_000),
+            ("學生", "hok6 saang1", "student; pupil", 800),
+            ("學", "hok6", "to learn; to study", 300),
+            ("生", "saang1", "to be born; raw", 400),
+            ("師", "si1", "teacher; master", 700),
+        ] {
+            builder.entries.push(DictionaryEntry::new(traditional, jyutping, EntrySource::CEDict, StringVecSet::single(english.to_owned()), cost));
+        }
+
+        let dict = CompiledDictionary::from_builder(builder);
+        let ids = |res: &crate::search::SearchResult| -> Vec<(usize, u32)> {
+            res.matches.iter().map(|x| (x.match_obj.entry_id, x.match_obj.cost_info.total())).collect()
+        };
+
+        for query in ["lou", "l", "s", "h", "tea", "stu", "老", "學生", "lou si", "saang"] {
+            let all = dict.search(query, 100, Box::new(TestStopwatch));
+            assert!(all.exhaustive);
+
+            for k in 1..4 {
+                let top = dict.search(query, k, Box::new(TestStopwatch));
+                let expected: Vec<_> = ids(&all).into_iter().take(k).collect();
+                assert_eq!(expected, ids(&top), "query '{}' k {}", query, k);
+            }
+        }
+    }
+
+    #[test]
+    fn test_search_stops_early() {
+        use crate::builder::DictionaryEntry;
+        use crate::search::{CancellationToken, SearchOptions};
+        use crate::StringVecSet;
+
+        // Each reading takes a millisecond
+        struct CountingStopwatch(std::cell::Cell<i32>);
+
+        impl Stopwatch for CountingStopwatch {
+            fn elapsed_ms(&self) -> i32 {
+                let elapsed = self.0.get();
+                self.0.set(elapsed + 1);
+                elapsed
+            }
+        }
+
+        let mut builder = Builder::default();
+        for i in 0..1_000 {
+            builder.entries.push(DictionaryEntry::new("路", "lou6", EntrySource::CEDict, StringVecSet::single(format!("road {}", i)), 100 + i));
+        }
+
+        let dict = CompiledDictionary::from_builder(builder);
+        let config = RankingConfig::default();
+
+        let all = dict.search_with_options("lou", &SearchOptions::new(2_000), &config, &CountingStopwatch(Default::default()));
+        assert_eq!(1_000, all.matches.len());
+        assert!(all.exhaustive);
+        assert!(!all.truncated);
+
+        // Two readings for the timings before the scan, then one per check
+        let options = SearchOptions {
+            time_budget_ms: Some(3),
+            ..SearchOptions::new(2_000)
+        };
+
+        let partial = dict.search_with_options("lou", &options, &config, &CountingStopwatch(Default::default()));
+        assert!(partial.truncated);
+        assert!(!partial.exhaustive);
+        assert_eq!(256, partial.matches.len());
+        // Entries it didn't reach could rank anywhere, a cursor after the last match would skip them
+        assert!(partial.next_cursor.is_none());
+
+        let partial_ids: Vec<usize> = partial.matches.iter().map(|x| x.match_obj.entry_id).collect();
+        let all_ids: Vec<usize> = all.matches.iter().take(256).map(|x| x.match_obj.entry_id).collect();
+        assert_eq!(all_ids, partial_ids);
+
+        // An exact match after many partial ones, past where the budget runs out
+        let mut builder = Builder::default();
+        for i in 0..600 {
+            builder.entries.push(DictionaryEntry::new("老", "loum2", EntrySource::CEDict, StringVecSet::single(format!("partial {}", i)), 100 + i));
+        }
+        builder.entries.push(DictionaryEntry::new("老", "lou5", EntrySource::CEDict, StringVecSet::single("old".to_owned()), 1_000));
+
+        let partials = CompiledDictionary::from_builder(builder);
+        let exact_id = partials.entries.len() - 1;
+        let first = partials.search_with_options("lou5", &SearchOptions::new(10), &config, &TestStopwatch);
+        assert_eq!(exact_id, first.matches[0].match_obj.entry_id);
+
+        let options = SearchOptions {
+            time_budget_ms: Some(3),
+            ..SearchOptions::new(10)
+        };
+
+        let truncated = partials.search_with_options("lou5", &options, &config, &CountingStopwatch(Default::default()));
+        assert!(truncated.truncated);
+        assert!(truncated.matches.iter().all(|x| x.match_obj.entry_id != exact_id));
+        assert!(truncated.next_cursor.is_none());
+
+        let cancellation = CancellationToken::new();
+        let options = SearchOptions {
+            cancellation: Some(cancellation.clone()),
+            ..SearchOptions::new(10)
+        };
+
+        assert!(!dict.search_with_options("lou", &options, &config, &TestStopwatch).truncated);
+
+        cancellation.cancel();
+        let cancelled = dict.search_with_options("lou", &options, &config, &TestStopwatch);
+        assert!(cancelled.truncated);
+        assert!(cancelled.matches.is_empty());
+    }
 }
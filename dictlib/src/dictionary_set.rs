@@ -0,0 +1,219 @@
+use crate::builder::Builder;
+use crate::compiled_dictionary::CompiledDictionary;
+use crate::rendered_result::RenderedResult;
+use crate::search::{MatchWithHitInfo, PageRequest, RankingConfig, SearchCursor, SearchOptions, SearchResult};
+use crate::search_session::SearchSession;
+use crate::Stopwatch;
+
+// Source name shown for entries from a runtime user dictionary
+pub const USER_DICTIONARY_NAME: &str = "user";
+
+/// Several dictionaries searched together, the first is the main dictionary and
+/// any others are small overlays such as a user's personal words.
+/// Matches from every dictionary are ranked together by cost, match_obj.dictionary_id
+/// says which dictionary the entry_id belongs to.
+#[derive(Debug)]
+pub struct DictionarySet {
+    pub dictionaries: Vec<CompiledDictionary>,
+}
+
+impl DictionarySet {
+    pub fn new(main: CompiledDictionary) -> Self {
+        Self {
+            dictionaries: vec![main],
+        }
+    }
+
+    pub fn main(&self) -> &CompiledDictionary {
+        &self.dictionaries[0]
+    }
+
+    pub fn main_mut(&mut self) -> &mut CompiledDictionary {
+        &mut self.dictionaries[0]
+    }
+
+    pub fn get(&self, dictionary_id: usize) -> &CompiledDictionary {
+        &self.dictionaries[dictionary_id]
+    }
+
+    /// Returns the dictionary_id of the overlay
+    pub fn add_overlay(&mut self, dict: CompiledDictionary) -> usize {
+        self.dictionaries.push(dict);
+        self.dictionaries.len() - 1
+    }
+
+    pub fn clear_overlays(&mut self) {
+        self.dictionaries.truncate(1);
+    }
+
+    /// Replace any overlays with a user dictionary from a json array of custom entries, see Builder::parse_custom_json
+    pub fn set_user_dictionary_json(&mut self, json: &str) -> serde_json::Result<()> {
+        let mut builder = Builder::default();
+        builder.parse_custom_json(json, USER_DICTIONARY_NAME)?;
+
+        self.clear_overlays();
+        if (!builder.entries.is_empty()) {
+            self.add_overlay(CompiledDictionary::from_builder(builder));
+        }
+
+        Ok(())
+    }
+
+    pub fn search(&self, s: &str, max_results: usize, stopwatch: Box<dyn Stopwatch>) -> SearchResult {
+        self.search_with_config(s, max_results, &RankingConfig::default(), stopwatch)
+    }
+
+    pub fn search_with_config(&self, s: &str, max_results: usize, config: &RankingConfig, stopwatch: Box<dyn Stopwatch>) -> SearchResult {
+        self.search_page(s, max_results, None, config, stopwatch.as_ref())
+    }
+
+    /// The page of max_results matches after cursor across all dictionaries, see CompiledDictionary::search_page
+    pub fn search_page(&self, s: &str, max_results: usize, cursor: Option<&SearchCursor>, config: &RankingConfig, stopwatch: &dyn Stopwatch) -> SearchResult {
+        let options = SearchOptions {
+            cursor: cursor.copied(),
+            ..SearchOptions::new(max_results)
+        };
+
+        self.search_with_options(s, &options, config, stopwatch)
+    }
+
+    /// The time budget covers the whole set, a dictionary searched after it runs out stops straight away
+    pub fn search_with_options(&self, s: &str, options: &SearchOptions, config: &RankingConfig, stopwatch: &dyn Stopwatch) -> SearchResult {
+        self.merge_pages(options.max_results, stopwatch, |dictionary_id, dict| {
+            dict.search_in_set(s, options, config, stopwatch, dictionary_id)
+        })
+    }
+
+    /// As search_with_options, reusing work from the session's previous query, see SearchSession
+    pub fn search_with_session(&self, session: &mut SearchSession, s: &str, options: &SearchOptions, config: &RankingConfig, stopwatch: &dyn Stopwatch) -> SearchResult {
+        self.merge_pages(options.max_results, stopwatch, |dictionary_id, dict| {
+            let page = PageRequest {
+                options,
+                dictionary_id,
+            };
+
+            session.search_dictionary(dict, s, &page, config, stopwatch)
+        })
+    }
+
+    fn merge_pages(&self, max_results: usize, stopwatch: &dyn Stopwatch, mut search_dictionary: impl FnMut(usize, &CompiledDictionary) -> SearchResult) -> SearchResult {
+        let mut result = SearchResult::default();
+        let mut matches: Vec<MatchWithHitInfo> = Vec::new();
+        let mut has_more = false;
+        result.exhaustive = true;
+
+        for (dictionary_id, dict) in self.dictionaries.iter().enumerate() {
+            let dict_result = search_dictionary(dictionary_id, dict);
+
+            // Timings are elapsed since the start so the main dictionary's are the interesting ones
+            if (dictionary_id == 0) {
+                result.timings = dict_result.timings;
+            }
+
+            result.internal_candidates += dict_result.internal_candidates;
+            result.exhaustive &= dict_result.exhaustive;
+            result.truncated |= dict_result.truncated;
+            has_more |= dict_result.next_cursor.is_some();
+            matches.extend(dict_result.matches);
+        }
+
+        // Each dictionary returned its own best page, the merged page is the best of those
+        matches.sort_by_key(|x| x.match_obj.sort_key());
+        has_more |= matches.len() > max_results;
+        matches.truncate(max_results);
+
+        // As for a single dictionary, a truncated page can't be continued
+        if (has_more && !result.truncated) {
+            result.next_cursor = matches.last().map(|x| SearchCursor::after(&x.match_obj));
+        }
+
+        result.timings.rank = stopwatch.elapsed_ms();
+        result.matches = matches;
+
+        result
+    }
+
+    pub fn render(&self, m: &MatchWithHitInfo) -> RenderedResult {
+        RenderedResult::from_match(m, self.get(m.match_obj.dictionary_id))
+    }
+
+    /// None if either id is out of range
+    pub fn render_entry(&self, dictionary_id: usize, entry_id: usize) -> Option<RenderedResult> {
+        let dict = self.dictionaries.get(dictionary_id)?;
+        if (entry_id >= dict.entries.len()) {
+            return None;
+        }
+
+        Some(RenderedResult::from_entry(entry_id, dict))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiled_dictionary::tests::create_test_dict;
+    use crate::EntrySource;
+
+    struct TestStopwatch;
+
+    impl Stopwatch for TestStopwatch {
+        fn elapsed_ms(&self) -> i32 {
+            0
+        }
+    }
+
+    #[test]
+    fn search_merges_overlay() {
+        let mut set = DictionarySet::new(create_test_dict());
+        set.set_user_dictionary_json(r#"[
+            { "traditional": "老豆", "jyutping": "lou5 dau6", "definitions": ["dad"], "cost": 50 },
+            { "traditional": "老細", "jyutping": "lou5 sai3", "definitions": ["boss"], "cost": 5000 }
+        ]"#).unwrap();
+
+        assert_eq!(2, set.dictionaries.len());
+
+        let result = set.search("lou", 8, Box::new(TestStopwatch));
+        let found: Vec<(usize, String)> = result.matches.iter()
+            .map(|m| (m.match_obj.dictionary_id, set.get(m.match_obj.dictionary_id).get_display_entry(m.match_obj.entry_id).jyutping))
+            .collect();
+
+        // Ranked by cost across both dictionaries
+        assert_eq!(vec![
+            (1, "lou5 dau6".to_owned()),
+            (0, "lou5 si1".to_owned()),
+            (1, "lou5 sai3".to_owned()),
+        ], found);
+
+        let rendered = set.render(&result.matches[0]);
+        assert!(rendered.is_custom);
+        assert_eq!(vec![EntrySource::Custom(USER_DICTIONARY_NAME.to_owned())], rendered.entry_sources);
+
+        let entry = set.render_entry(1, 0).unwrap();
+        assert_eq!("老豆", entry.characters);
+        assert_eq!("lou5 dau6", entry.jyutping);
+        assert!(set.render_entry(1, 2).is_none());
+        assert!(set.render_entry(2, 0).is_none());
+
+        let limited = set.search("lou", 2, Box::new(TestStopwatch));
+        assert_eq!(2, limited.matches.len());
+
+        // The next page continues across both dictionaries
+        let next = set.search_page("lou", 2, limited.next_cursor.as_ref(), &RankingConfig::default(), &TestStopwatch);
+        assert_eq!(vec![(1, "lou5 sai3".to_owned())], next.matches.iter()
+            .map(|m| (m.match_obj.dictionary_id, set.get(m.match_obj.dictionary_id).get_display_entry(m.match_obj.entry_id).jyutping))
+            .collect::<Vec<_>>());
+        assert!(next.next_cursor.is_none());
+    }
+
+    #[test]
+    fn replace_user_dictionary() {
+        let mut set = DictionarySet::new(create_test_dict());
+        set.set_user_dictionary_json(r#"[{ "traditional": "老豆", "jyutping": "lou5 dau6", "definitions": ["dad"] }]"#).unwrap();
+        assert!(set.set_user_dictionary_json("not json").is_err());
+        assert_eq!(2, set.dictionaries.len());
+
+        set.set_user_dictionary_json("[]").unwrap();
+        assert_eq!(1, set.dictionaries.len());
+        assert_eq!(1, set.search("lou", 8, Box::new(TestStopwatch)).matches.len());
+    }
+}
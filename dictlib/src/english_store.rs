@@ -0,0 +1,343 @@
+use std::collections::BTreeMap;
+
+use crate::data_reader::DataReader;
+use crate::data_writer::DataWriter;
+use crate::stats::DictionaryStats;
+use crate::string_search::string_indexof_linear_ignorecase;
+
+pub const ENGLISH_BLOB_HEADER: &[u8] = b"en_data_";
+
+// vbyte reads always load 8 bytes
+const VBYTE_PADDING: usize = 8;
+
+/// English definitions stored as word tokens.
+/// A token is a run of non-whitespace followed by any whitespace after it, so concatenating a
+/// definition's tokens gives back its text exactly. Tokens are numbered most frequent first so
+/// common words take a single byte as a vbyte id.
+#[derive(Debug, Clone)]
+pub struct EnglishStore
+{
+    pub tokens: Vec<u8>,
+    pub token_starts: Vec<u32>,
+
+    // Token ids of every definition followed by padding, definition i is data[starts[i]..starts[i+1]]
+    pub data: Vec<u8>,
+    pub definition_starts: Vec<u32>,
+}
+
+impl Default for EnglishStore {
+    fn default() -> Self {
+        Self {
+            tokens: Vec::new(),
+            token_starts: vec![0],
+            data: vec![0; VBYTE_PADDING],
+            definition_starts: vec![0],
+        }
+    }
+}
+
+fn tokenize(definition: &str) -> impl Iterator<Item = &str> {
+    let bytes = definition.as_bytes();
+    let mut start = 0;
+    std::iter::from_fn(move || {
+        if (start == bytes.len()) {
+            return None;
+        }
+
+        let mut end = start;
+        while (end < bytes.len() && !bytes[end].is_ascii_whitespace()) {
+            end += 1;
+        }
+        while (end < bytes.len() && bytes[end].is_ascii_whitespace()) {
+            end += 1;
+        }
+
+        // Splitting on ascii bytes always leaves valid utf8
+        let token = &definition[start..end];
+        start = end;
+        Some(token)
+    })
+}
+
+impl EnglishStore {
+    pub fn from_definitions(definitions: &[&str]) -> Self {
+        let mut counts : BTreeMap<&str, u32> = BTreeMap::new();
+        for definition in definitions {
+            for token in tokenize(definition) {
+                *counts.entry(token).or_default() += 1;
+            }
+        }
+
+        // Ties broken by the token text so builds are repeatable
+        let mut ordered : Vec<(&str, u32)> = counts.into_iter().collect();
+        ordered.sort_by(|x, y| y.1.cmp(&x.1).then(x.0.cmp(y.0)));
+
+        let mut store = Self::default();
+        let mut token_ids : BTreeMap<&str, u32> = BTreeMap::new();
+        for (id, (token, _)) in ordered.iter().enumerate() {
+            token_ids.insert(token, id as u32);
+            store.tokens.extend_from_slice(token.as_bytes());
+            store.token_starts.push(store.tokens.len() as u32);
+        }
+
+        store.data.clear();
+        store.definition_starts.clear();
+        for definition in definitions {
+            store.definition_starts.push(store.data.len() as u32);
+            for token in tokenize(definition) {
+                let (len, encoded) = crate::vbyte::encode_vbyte(token_ids[token] as u64);
+                store.data.extend_from_slice(&encoded.to_le_bytes()[..len as usize]);
+            }
+        }
+        store.definition_starts.push(store.data.len() as u32);
+        store.data.extend_from_slice(&[0; VBYTE_PADDING]);
+
+        store
+    }
+
+    pub fn definition_count(&self) -> usize {
+        self.definition_starts.len() - 1
+    }
+
+    pub fn token_count(&self) -> usize {
+        self.token_starts.len() - 1
+    }
+
+    pub fn token(&self, id: u32) -> &[u8] {
+        &self.tokens[self.token_starts[id as usize] as usize..self.token_starts[id as usize + 1] as usize]
+    }
+
+    pub fn definition_tokens(&self, i: u32) -> DefinitionTokens<'_> {
+        DefinitionTokens {
+            reader: DataReader::new_at(&self.data, self.definition_starts[i as usize] as usize),
+            end: self.definition_starts[i as usize + 1] as usize,
+        }
+    }
+
+    pub fn definition(&self, i: u32) -> String {
+        let mut text = Vec::new();
+        for id in self.definition_tokens(i) {
+            text.extend_from_slice(self.token(id));
+        }
+
+        // Tokens were cut from strings on ascii boundaries
+        unsafe { String::from_utf8_unchecked(text) }
+    }
+
+    /// First match of term in definitions as an offset into their concatenated text,
+    /// along with whether the match starts a word
+    pub fn find(&self, definitions: std::ops::Range<u32>, term: &EnglishQueryTerm) -> Option<(usize, bool)> {
+        let mut offset = 0;
+        for i in definitions {
+            for id in self.definition_tokens(i) {
+                let pos = term.token_positions[id as usize];
+                if (pos != u32::MAX) {
+                    let pos = pos as usize;
+                    let word_start = pos == 0 || {
+                        let c = self.token(id)[pos - 1];
+                        c.is_ascii_whitespace() || c == b'-'
+                    };
+
+                    return Some((offset + pos, word_start));
+                }
+
+                offset += self.token(id).len();
+            }
+        }
+
+        None
+    }
+
+    /// The definitions in range, and the token table if with_tokens.
+    /// Chunks are written without tokens, they use the table from the core.
+    pub fn write<T : std::io::Write>(&self, writer : &mut DataWriter<T>, definitions : std::ops::Range<u32>, with_tokens : bool, stats : &mut DictionaryStats) -> std::io::Result<()>
+    {
+        stats.measure("english tokens", writer, |w| {
+            w.write_bytes(ENGLISH_BLOB_HEADER)?;
+
+            let token_count = if (with_tokens) { self.token_count() } else { 0 };
+            let tokens_end = self.token_starts[token_count] as usize;
+            w.write_bytes_and_length(&self.tokens[..tokens_end])?;
+            w.write_u32(token_count as u32)?;
+            for id in 0..token_count {
+                w.write_vbyte(self.token(id as u32).len() as u64)?;
+            }
+
+            Ok(())
+        })?;
+
+        let starts = &self.definition_starts[definitions.start as usize..=definitions.end as usize];
+        let data_start = starts[0];
+        let data_end = *starts.last().unwrap();
+
+        stats.measure("english data", writer, |w| {
+            w.write_bytes_and_length(&self.data[data_start as usize..data_end as usize])
+        })?;
+
+        stats.measure("english starts", writer, |w| {
+            let mut prev_start = data_start;
+            w.write_u32(starts.len() as u32)?;
+            for start in starts
+            {
+                let delta = *start - prev_start;
+                prev_start = *start;
+                w.write_vbyte(delta as u64)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    pub fn read(reader : &mut DataReader) -> Self
+    {
+        let mut store = Self::default();
+        store.append(reader);
+        store
+    }
+
+    /// Add the definitions and any tokens written by write after the existing ones
+    pub fn append(&mut self, reader : &mut DataReader)
+    {
+        let blob_header = reader.read_bytes_len(8);
+        debug_log!("blob_header '{}'", std::str::from_utf8(blob_header).expect("Not utf8"));
+        assert!(blob_header == ENGLISH_BLOB_HEADER);
+
+        let tokens_size = reader.read_u32();
+        self.tokens.extend_from_slice(reader.read_bytes_len(tokens_size as usize));
+
+        let token_count = reader.read_u32();
+        let mut token_end = *self.token_starts.last().unwrap();
+        for _ in 0..token_count {
+            token_end += reader.read_vbyte() as u32;
+            self.token_starts.push(token_end);
+        }
+        assert_eq!(self.tokens.len(), token_end as usize);
+
+        let data_size = reader.read_u32();
+        let data = reader.read_bytes_len(data_size as usize);
+
+        let starts_count = reader.read_u32() as usize;
+        if (starts_count == 0) {
+            return;
+        }
+
+        // The new definitions begin where the current end marker and padding are
+        let offset = self.definition_starts.pop().unwrap();
+        self.data.truncate(offset as usize);
+        self.data.extend_from_slice(data);
+        self.data.extend_from_slice(&[0; VBYTE_PADDING]);
+
+        let mut prev_start = offset as u64;
+        for _ in 0..starts_count
+        {
+            let start = prev_start + reader.read_vbyte();
+            prev_start = start;
+            self.definition_starts.push(start as u32);
+        }
+    }
+}
+
+pub struct DefinitionTokens<'a> {
+    reader: DataReader<'a>,
+    end: usize,
+}
+
+impl Iterator for DefinitionTokens<'_> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        if (self.reader.position >= self.end) {
+            return None;
+        }
+
+        Some(self.reader.read_vbyte() as u32)
+    }
+}
+
+/// Where one whitespace separated part of a query first occurs in each token
+pub struct EnglishQueryTerm {
+    pub len: usize,
+
+    // u32::MAX for tokens that don't contain the term
+    pub token_positions: Vec<u32>,
+}
+
+impl EnglishQueryTerm {
+    pub fn create(term: &str, store: &EnglishStore) -> Self {
+        let mut token_positions = Vec::with_capacity(store.token_count());
+        for id in 0..store.token_count() {
+            let pos = string_indexof_linear_ignorecase(term, store.token(id as u32));
+            token_positions.push(pos.map(|x| x as u32).unwrap_or(u32::MAX));
+        }
+
+        Self {
+            len: term.len(),
+            token_positions,
+        }
+    }
+
+    pub fn create_all(s: &str, store: &EnglishStore) -> Vec<Self> {
+        s.split_ascii_whitespace().map(|x| Self::create(x, store)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenize_keeps_whitespace() {
+        assert_eq!(vec!["to ", "teach"], tokenize("to teach").collect::<Vec<_>>());
+        assert_eq!(vec!["  ", "a  ", "b "], tokenize("  a  b ").collect::<Vec<_>>());
+        assert!(tokenize("").next().is_none());
+    }
+
+    #[test]
+    fn roundtrip_definitions() {
+        let definitions = ["to teach", "teacher", "", "(literary) to teach  教", "to"];
+        let store = EnglishStore::from_definitions(&definitions);
+
+        // Most common first, the final "to" is a different token without the space
+        assert_eq!(b"to ", store.token(0));
+        for (i, definition) in definitions.iter().enumerate() {
+            assert_eq!(*definition, store.definition(i as u32));
+        }
+
+        let mut writer = DataWriter {
+            write_len: 0,
+            inner: std::io::BufWriter::new(Vec::new()),
+        };
+        let mut stats = DictionaryStats::default();
+        store.write(&mut writer, 0..2, true, &mut stats).unwrap();
+        store.write(&mut writer, 2..5, false, &mut stats).unwrap();
+        writer.write_u64(0).unwrap();
+        let buffer = writer.inner.into_inner().unwrap();
+
+        let mut reader = DataReader::new(&buffer);
+        let mut read = EnglishStore::read(&mut reader);
+        assert_eq!(2, read.definition_count());
+        read.append(&mut reader);
+
+        assert_eq!(store.tokens, read.tokens);
+        assert_eq!(store.token_starts, read.token_starts);
+        assert_eq!(store.data, read.data);
+        assert_eq!(store.definition_starts, read.definition_starts);
+    }
+
+    #[test]
+    fn find_in_definitions() {
+        let store = EnglishStore::from_definitions(&["old man", "father-in-law", "teacher"]);
+        let find = |range, term| store.find(range, &EnglishQueryTerm::create(term, &store));
+
+        assert_eq!(Some((4, true)), find(0..3, "MAN"));
+        assert_eq!(Some((17, true)), find(0..3, "law"));
+        assert_eq!(Some((11, false)), find(0..3, "er-"));
+        assert_eq!(Some((7, true)), find(1..3, "in"));
+        assert_eq!(Some((10, false)), find(0..3, "her"));
+
+        // Definitions are searched separately
+        assert_eq!(None, find(0..3, "lawteach"));
+        assert_eq!(None, find(0..3, "old man"));
+        assert_eq!(None, find(0..2, "teach"));
+    }
+}
@@ -0,0 +1,356 @@
+use crate::annotations::EntryAnnotations;
+use crate::compiled_dictionary::{CompiledDictionaryEntry, Jyutping, SourceGroup, FLAG_HAS_ANNOTATIONS, FLAG_MERGED_SOURCES, FLAG_SOURCE_CUSTOM};
+use crate::data_reader::DataReader;
+use crate::data_writer::DataWriter;
+use crate::stats::DictionaryStats;
+
+pub const ENTRY_CODEC_HEADER: &[u8] = b"entcodec";
+
+const TONE_COUNT: usize = 7;
+
+// Entry lengths byte
+//     bits 0-2 character count, LENGTH_CHARACTERS_MORE if a vbyte count follows
+//     bit 3    set if the jyutping count differs from the character count, a vbyte count follows
+//     bits 4-7 definition count, LENGTH_DEFINITIONS_MORE if a vbyte count follows
+const LENGTH_CHARACTERS_MORE: u8 = 0x7;
+const LENGTH_JYUTPING_DIFFERS: u8 = 0x8;
+const LENGTH_DEFINITIONS_MORE: u8 = 0xF;
+
+/// Entries are written with characters and syllables renumbered by how many entries use them,
+/// so the common ones take a single vbyte. A syllable is a jyutping base and tone together.
+/// The same codec has to be used for the core and every chunk of a dictionary.
+#[derive(Debug, Clone, Default)]
+pub struct EntryCodec
+{
+    // Character ids, most used first
+    pub characters: Vec<u16>,
+    pub syllables: Vec<Jyutping>,
+}
+
+// Bytes written for each part of the entries
+#[derive(Debug, Default)]
+struct EntrySizes
+{
+    flags: usize,
+    lengths: usize,
+    sources: usize,
+    characters: usize,
+    jyutping: usize,
+    costs: usize,
+    annotations: usize,
+}
+
+// Runs f, adding what it wrote to size
+fn measure<T : std::io::Write>(writer : &mut DataWriter<T>, size : &mut usize, f : impl FnOnce(&mut DataWriter<T>) -> std::io::Result<()>) -> std::io::Result<()> {
+    let start = writer.write_len;
+    f(writer)?;
+    *size += writer.write_len - start;
+    Ok(())
+}
+
+fn syllable_index(j: Jyutping) -> usize {
+    j.base as usize * TONE_COUNT + j.tone as usize
+}
+
+// Ids in order of use, ties broken by id so builds are repeatable
+fn order_by_count(counts: &[u32]) -> Vec<usize> {
+    let mut order : Vec<usize> = (0..counts.len()).filter(|x| counts[*x] > 0).collect();
+    order.sort_by(|x, y| counts[*y].cmp(&counts[*x]).then(x.cmp(y)));
+    order
+}
+
+// Inverse of an ordering, u32::MAX for ids that never appear
+fn ranks(order: impl Iterator<Item = usize>, count: usize) -> Vec<u32> {
+    let mut ranks = vec![u32::MAX; count];
+    for (rank, id) in order.enumerate() {
+        ranks[id] = rank as u32;
+    }
+    ranks
+}
+
+impl EntryCodec {
+    pub fn from_entries(entries: &[CompiledDictionaryEntry], character_count: usize, jyutping_base_count: usize) -> Self {
+        let mut character_counts = vec![0u32; character_count];
+        let mut syllable_counts = vec![0u32; jyutping_base_count * TONE_COUNT];
+        for e in entries {
+            for c in &e.characters {
+                character_counts[*c as usize] += 1;
+            }
+
+            for j in &e.jyutping {
+                syllable_counts[syllable_index(*j)] += 1;
+            }
+        }
+
+        Self {
+            characters: order_by_count(&character_counts).into_iter().map(|x| x as u16).collect(),
+            syllables: order_by_count(&syllable_counts).into_iter().map(|x| Jyutping {
+                base: (x / TONE_COUNT) as u16,
+                tone: (x % TONE_COUNT) as u8,
+            }).collect(),
+        }
+    }
+
+    pub fn write<T : std::io::Write>(&self, writer : &mut DataWriter<T>, stats : &mut DictionaryStats) -> std::io::Result<()>
+    {
+        stats.measure("entry codec", writer, |w| {
+            w.write_bytes(ENTRY_CODEC_HEADER)?;
+
+            w.write_u32(self.characters.len() as u32)?;
+            for c in &self.characters {
+                w.write_u16(*c)?;
+            }
+
+            w.write_u32(self.syllables.len() as u32)?;
+            for j in &self.syllables {
+                w.write_u16(j.pack())?;
+            }
+
+            Ok(())
+        })
+    }
+
+    pub fn read(reader : &mut DataReader) -> Self
+    {
+        let header = reader.read_bytes_len(8);
+        assert_eq!(header, ENTRY_CODEC_HEADER);
+
+        let character_count = reader.read_u32();
+        let characters = (0..character_count).map(|_| reader.read_u16()).collect();
+
+        let syllable_count = reader.read_u32();
+        let syllables = (0..syllable_count).map(|_| Jyutping::unpack(reader.read_u16())).collect();
+
+        Self {
+            characters,
+            syllables,
+        }
+    }
+
+    pub fn write_entries<T : std::io::Write>(&self, writer : &mut DataWriter<T>, entries : &[CompiledDictionaryEntry], stats : &mut DictionaryStats) -> std::io::Result<()>
+    {
+        let max_character = self.characters.iter().max().map(|x| *x as usize + 1).unwrap_or(0);
+        let character_ranks = ranks(self.characters.iter().map(|x| *x as usize), max_character);
+        let max_syllable = self.syllables.iter().map(|x| syllable_index(*x) + 1).max().unwrap_or(0);
+        let syllable_ranks = ranks(self.syllables.iter().map(|x| syllable_index(*x)), max_syllable);
+
+        let mut sizes = EntrySizes::default();
+
+        measure(writer, &mut sizes.lengths, |w| w.write_u32(entries.len() as u32))?;
+        let mut prev_english_start = entries.first().map(|x| x.english_start).unwrap_or(0);
+        let mut prev_cost = 0;
+        for e in entries
+        {
+            measure(writer, &mut sizes.flags, |w| w.write_u8(e.flags))?;
+
+            assert!(prev_english_start <= e.english_start);
+            prev_english_start = e.english_start;
+            let definition_count = e.english_end - e.english_start;
+
+            measure(writer, &mut sizes.lengths, |w| {
+                let char_count = e.characters.len() as u8;
+                let mut lengths = char_count.min(LENGTH_CHARACTERS_MORE);
+                if (e.jyutping.len() != e.characters.len()) {
+                    lengths |= LENGTH_JYUTPING_DIFFERS;
+                }
+                lengths |= (definition_count.min(LENGTH_DEFINITIONS_MORE as u32) as u8) << 4;
+                w.write_u8(lengths)?;
+
+                assert!(e.characters.len() < 128);
+                assert!(e.jyutping.len() < 256);
+                if (char_count >= LENGTH_CHARACTERS_MORE) {
+                    w.write_vbyte(char_count as u64)?;
+                }
+                if (lengths & LENGTH_JYUTPING_DIFFERS != 0) {
+                    w.write_vbyte(e.jyutping.len() as u64)?;
+                }
+                if (definition_count >= LENGTH_DEFINITIONS_MORE as u32) {
+                    w.write_vbyte(definition_count as u64)?;
+                }
+                Ok(())
+            })?;
+
+            measure(writer, &mut sizes.sources, |w| {
+                if (e.flags & FLAG_MERGED_SOURCES != 0) {
+                    w.write_u8(e.source_groups.len() as u8)?;
+                    for group in &e.source_groups {
+                        w.write_u8(group.source)?;
+                        if (group.source & FLAG_SOURCE_CUSTOM != 0) {
+                            w.write_u8(group.custom_source)?;
+                        }
+                        w.write_u8(group.definition_count)?;
+                    }
+                }
+                else if (e.flags & FLAG_SOURCE_CUSTOM != 0) {
+                    w.write_u8(e.custom_source)?;
+                }
+                Ok(())
+            })?;
+
+            measure(writer, &mut sizes.characters, |w| {
+                for c in &e.characters {
+                    w.write_vbyte(character_ranks[*c as usize] as u64)?;
+                }
+                Ok(())
+            })?;
+
+            measure(writer, &mut sizes.jyutping, |w| {
+                for j in &e.jyutping {
+                    w.write_vbyte(syllable_ranks[syllable_index(*j)] as u64)?;
+                }
+                Ok(())
+            })?;
+
+            measure(writer, &mut sizes.costs, |w| w.write_vbyte((e.cost - prev_cost) as u64))?;
+            prev_cost = e.cost;
+
+            if (e.flags & FLAG_HAS_ANNOTATIONS != 0) {
+                measure(writer, &mut sizes.annotations, |w| e.annotations.as_ref().unwrap().write(w))?;
+            }
+        }
+
+        stats.add_section("entry flags", sizes.flags);
+        stats.add_section("entry lengths", sizes.lengths);
+        stats.add_section("entry sources", sizes.sources);
+        stats.add_section("entry characters", sizes.characters);
+        stats.add_section("entry jyutping", sizes.jyutping);
+        stats.add_section("entry costs", sizes.costs);
+        stats.add_section("entry annotations", sizes.annotations);
+
+        Ok(())
+    }
+
+    // Definitions are numbered on from english_start
+    pub fn read_entries(&self, reader : &mut DataReader, mut english_start : u32) -> Vec<CompiledDictionaryEntry>
+    {
+        let entry_count = reader.read_u32();
+        let mut entries = Vec::with_capacity(entry_count as usize);
+
+        let mut prev_cost = 0;
+        for _ in 0..entry_count {
+            let mut entry = CompiledDictionaryEntry {
+                flags: reader.read_u8(),
+                ..Default::default()
+            };
+
+            let lengths = reader.read_u8();
+            let mut char_count = (lengths & LENGTH_CHARACTERS_MORE) as usize;
+            if (char_count == LENGTH_CHARACTERS_MORE as usize) {
+                char_count = reader.read_vbyte() as usize;
+            }
+            let jyutping_count = if (lengths & LENGTH_JYUTPING_DIFFERS != 0) {
+                reader.read_vbyte() as usize
+            }
+            else {
+                char_count
+            };
+            let mut definition_count = (lengths >> 4) as u32;
+            if (definition_count == LENGTH_DEFINITIONS_MORE as u32) {
+                definition_count = reader.read_vbyte() as u32;
+            }
+
+            if (entry.flags & FLAG_MERGED_SOURCES != 0) {
+                let group_count = reader.read_u8();
+                for _ in 0..group_count {
+                    let source = reader.read_u8();
+                    let custom_source = if (source & FLAG_SOURCE_CUSTOM != 0) {
+                        reader.read_u8()
+                    }
+                    else {
+                        0
+                    };
+
+                    entry.source_groups.push(SourceGroup {
+                        source,
+                        custom_source,
+                        definition_count: reader.read_u8(),
+                    });
+                }
+            }
+            else if (entry.flags & FLAG_SOURCE_CUSTOM != 0) {
+                entry.custom_source = reader.read_u8();
+            }
+
+            entry.characters.reserve(char_count);
+            for _ in 0..char_count {
+                entry.characters.push(self.characters[reader.read_vbyte() as usize]);
+            }
+
+            entry.jyutping.reserve(jyutping_count);
+            for _ in 0..jyutping_count {
+                entry.jyutping.push(self.syllables[reader.read_vbyte() as usize]);
+            }
+
+            entry.english_start = english_start;
+            entry.english_end = english_start + definition_count;
+            english_start = entry.english_end;
+
+            let cost_delta = reader.read_vbyte() as u32;
+            entry.cost = prev_cost + cost_delta;
+            prev_cost = entry.cost;
+
+            if (entry.flags & FLAG_HAS_ANNOTATIONS != 0) {
+                entry.annotations = Some(Box::new(EntryAnnotations::read(reader)));
+            }
+
+            entries.push(entry);
+        }
+
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiled_dictionary::FLAG_SOURCE_CEDICT;
+
+    fn entry(characters: Vec<u16>, jyutping: Vec<(u16, u8)>, definition_count: u32, cost: u32) -> CompiledDictionaryEntry {
+        CompiledDictionaryEntry {
+            characters,
+            jyutping: jyutping.into_iter().map(|(base, tone)| Jyutping { base, tone }).collect(),
+            english_end: definition_count,
+            cost,
+            flags: FLAG_SOURCE_CEDICT,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn entries_roundtrip() {
+        let mut entries = vec![
+            entry(vec![3, 1], vec![(1, 5), (3, 1)], 1, 100),
+            entry(vec![3], vec![(1, 5)], 0, 150),
+            // Lengths that don't fit in the lengths byte
+            entry(vec![2; 9], vec![(0, 6); 10], 20, 200),
+            entry(vec![0, 3], vec![(2, 0)], 15, 1_000),
+        ];
+
+        let mut english_start = 0;
+        for e in &mut entries {
+            e.english_end += english_start;
+            e.english_start = english_start;
+            english_start = e.english_end;
+        }
+
+        let codec = EntryCodec::from_entries(&entries, 5, 4);
+        assert_eq!(vec![2, 3, 0, 1], codec.characters);
+        assert_eq!(Jyutping { base: 0, tone: 6 }, codec.syllables[0]);
+
+        let mut writer = DataWriter {
+            write_len: 0,
+            inner: std::io::BufWriter::new(Vec::new()),
+        };
+        let mut stats = DictionaryStats::default();
+        codec.write(&mut writer, &mut stats).unwrap();
+        codec.write_entries(&mut writer, &entries, &mut stats).unwrap();
+        writer.write_u64(0).unwrap();
+        let buffer = writer.inner.into_inner().unwrap();
+
+        let mut reader = DataReader::new(&buffer);
+        let read_codec = EntryCodec::read(&mut reader);
+        let read = read_codec.read_entries(&mut reader, 0);
+
+        assert_eq!(format!("{:?}", entries), format!("{:?}", read));
+    }
+}
@@ -0,0 +1,321 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::compiled_dictionary::CompiledDictionary;
+use crate::search::RankingConfig;
+use crate::Stopwatch;
+
+// How deep to look for expected entries when computing reciprocal rank
+pub const EVAL_MAX_RESULTS: usize = 50;
+
+/// A query with the entries we expect to see near the top of the results.
+///
+/// Golden files are tab separated, one query per line followed by the expected entries
+///     query \t expected0 \t expected1 ...
+/// where an expected entry is the traditional characters with an optional {jyutping}
+///     lou5 si1 \t 老師 {lou5 si1}
+/// Empty lines and lines starting with '#' are ignored.
+#[derive(Debug, Clone)]
+pub struct GoldenQuery {
+    pub query: String,
+    pub expected: Vec<ExpectedEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpectedEntry {
+    pub traditional: String,
+    pub jyutping: Option<String>,
+}
+
+impl ExpectedEntry {
+    pub fn parse(s: &str) -> Self {
+        let s = s.trim();
+        if let Some((traditional, rest)) = s.split_once('{') {
+            let jyutping = rest.trim_end().trim_end_matches('}');
+            Self {
+                traditional: traditional.trim().to_owned(),
+                jyutping: Some(jyutping.trim().to_owned()),
+            }
+        }
+        else {
+            Self {
+                traditional: s.to_owned(),
+                jyutping: None,
+            }
+        }
+    }
+
+    pub fn matches(&self, dict: &CompiledDictionary, entry_id: usize) -> bool {
+        let display = dict.get_display_entry(entry_id);
+        if (display.characters != self.traditional) {
+            return false;
+        }
+
+        if let Some(j) = &self.jyutping {
+            return j.eq_ignore_ascii_case(&display.jyutping);
+        }
+
+        true
+    }
+}
+
+impl GoldenQuery {
+    pub fn parse_str(data: &str) -> Vec<Self> {
+        let mut queries = Vec::new();
+        for line in data.lines() {
+            if (line.trim().is_empty() || line.starts_with('#')) {
+                continue;
+            }
+
+            let mut parts = line.split('\t');
+            let query = parts.next().unwrap().trim().to_owned();
+            let expected: Vec<ExpectedEntry> = parts.filter(|x| !x.trim().is_empty()).map(ExpectedEntry::parse).collect();
+            assert!(!expected.is_empty(), "Golden query '{}' has no expected entries", query);
+
+            queries.push(Self {
+                query,
+                expected,
+            });
+        }
+
+        queries
+    }
+
+    pub fn parse(path: &str) -> Vec<Self> {
+        let data = std::fs::read_to_string(path).unwrap();
+        Self::parse_str(&data)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryEvalResult {
+    pub query: String,
+    // 1-based rank of the first expected entry, None if not in the first EVAL_MAX_RESULTS
+    pub first_relevant_rank: Option<usize>,
+    pub precision_at_k: f32,
+    pub recall_at_k: f32,
+    pub reciprocal_rank: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvalReport {
+    pub k: usize,
+    pub queries: Vec<QueryEvalResult>,
+    pub mean_precision_at_k: f32,
+    pub mean_recall_at_k: f32,
+    pub mrr: f32,
+}
+
+struct NoopStopwatch;
+
+impl Stopwatch for NoopStopwatch {
+    fn elapsed_ms(&self) -> i32 {
+        0
+    }
+}
+
+pub fn evaluate(dict: &CompiledDictionary, queries: &[GoldenQuery], k: usize, config: &RankingConfig) -> EvalReport {
+    let mut results = Vec::with_capacity(queries.len());
+
+    for q in queries {
+        let search_result = dict.search_with_config(&q.query, EVAL_MAX_RESULTS.max(k), config, Box::new(NoopStopwatch));
+
+        let mut first_relevant_rank = None;
+        let mut relevant_in_k = 0;
+        let mut found_in_k = vec![false; q.expected.len()];
+
+        for (i, m) in search_result.matches.iter().enumerate() {
+            let mut relevant = false;
+            for (j, expected) in q.expected.iter().enumerate() {
+                if (expected.matches(dict, m.match_obj.entry_id)) {
+                    relevant = true;
+                    if (i < k) {
+                        found_in_k[j] = true;
+                    }
+                }
+            }
+
+            if (relevant) {
+                if (first_relevant_rank.is_none()) {
+                    first_relevant_rank = Some(i + 1);
+                }
+
+                if (i < k) {
+                    relevant_in_k += 1;
+                }
+            }
+        }
+
+        let found_count = found_in_k.iter().filter(|x| **x).count();
+
+        results.push(QueryEvalResult {
+            query: q.query.clone(),
+            first_relevant_rank,
+            precision_at_k: relevant_in_k as f32 / k as f32,
+            recall_at_k: found_count as f32 / q.expected.len() as f32,
+            reciprocal_rank: first_relevant_rank.map(|r| 1.0 / r as f32).unwrap_or(0.0),
+        });
+    }
+
+    let n = results.len().max(1) as f32;
+    EvalReport {
+        k,
+        mean_precision_at_k: results.iter().map(|x| x.precision_at_k).sum::<f32>() / n,
+        mean_recall_at_k: results.iter().map(|x| x.recall_at_k).sum::<f32>() / n,
+        mrr: results.iter().map(|x| x.reciprocal_rank).sum::<f32>() / n,
+        queries: results,
+    }
+}
+
+impl EvalReport {
+    pub fn parse(path: &str) -> Self {
+        let data = std::fs::read_to_string(path).unwrap();
+        serde_json::from_str(&data).unwrap_or_else(|e| panic!("Could not parse eval report {}: {}", path, e))
+    }
+
+    pub fn save(&self, path: &str) {
+        std::fs::write(path, serde_json::to_string_pretty(self).unwrap()).unwrap();
+    }
+
+    /// Compare per query ranks against a previously saved report.
+    pub fn diff(&self, baseline: &EvalReport) -> EvalDiff {
+        let mut diff = EvalDiff {
+            mrr_delta: self.mrr - baseline.mrr,
+            ..Default::default()
+        };
+
+        for q in &self.queries {
+            let Some(old) = baseline.queries.iter().find(|x| x.query == q.query) else {
+                diff.new_queries.push(q.query.clone());
+                continue;
+            };
+
+            // Treat not found as infinitely far down
+            let old_rank = old.first_relevant_rank.unwrap_or(usize::MAX);
+            let new_rank = q.first_relevant_rank.unwrap_or(usize::MAX);
+
+            let change = RankChange {
+                query: q.query.clone(),
+                old_rank: old.first_relevant_rank,
+                new_rank: q.first_relevant_rank,
+            };
+
+            if (new_rank > old_rank) {
+                diff.regressions.push(change);
+            }
+            else if (new_rank < old_rank) {
+                diff.improvements.push(change);
+            }
+        }
+
+        diff
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RankChange {
+    pub query: String,
+    pub old_rank: Option<usize>,
+    pub new_rank: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EvalDiff {
+    pub mrr_delta: f32,
+    pub regressions: Vec<RankChange>,
+    pub improvements: Vec<RankChange>,
+    pub new_queries: Vec<String>,
+}
+
+fn fmt_rank(rank: Option<usize>) -> String {
+    rank.map(|x| x.to_string()).unwrap_or_else(|| "-".to_owned())
+}
+
+impl fmt::Display for EvalReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<24} {:>6} {:>8} {:>8} {:>8}", "query", "rank", format!("P@{}", self.k), format!("R@{}", self.k), "RR")?;
+        for q in &self.queries {
+            writeln!(f, "{:<24} {:>6} {:>8.3} {:>8.3} {:>8.3}", q.query, fmt_rank(q.first_relevant_rank), q.precision_at_k, q.recall_at_k, q.reciprocal_rank)?;
+        }
+        writeln!(f, "{} queries, mean P@{} = {:.4}, mean R@{} = {:.4}, MRR = {:.4}",
+            self.queries.len(), self.k, self.mean_precision_at_k, self.k, self.mean_recall_at_k, self.mrr)
+    }
+}
+
+impl fmt::Display for EvalDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "MRR delta {:+.4}", self.mrr_delta)?;
+        for x in &self.regressions {
+            writeln!(f, "  regressed '{}' {} -> {}", x.query, fmt_rank(x.old_rank), fmt_rank(x.new_rank))?;
+        }
+        for x in &self.improvements {
+            writeln!(f, "  improved  '{}' {} -> {}", x.query, fmt_rank(x.old_rank), fmt_rank(x.new_rank))?;
+        }
+        for x in &self.new_queries {
+            writeln!(f, "  new query '{}'", x)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::Builder;
+    use crate::heuristics::HeuristicsConfig;
+    use crate::compiled_dictionary::tests::create_test_dict;
+
+    pub const TEST_DATA_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../test");
+
+    #[test]
+    fn parse_golden() {
+        let queries = GoldenQuery::parse_str("# comment\nlou5 si1\t老師 {lou5 si1}\t老師\n\nstudent\t學生\n");
+        assert_eq!(2, queries.len());
+        assert_eq!("lou5 si1", queries[0].query);
+        assert_eq!(ExpectedEntry { traditional: "老師".to_owned(), jyutping: Some("lou5 si1".to_owned()) }, queries[0].expected[0]);
+        assert_eq!(ExpectedEntry { traditional: "老師".to_owned(), jyutping: None }, queries[0].expected[1]);
+        assert_eq!("student", queries[1].query);
+    }
+
+    #[test]
+    fn metrics() {
+        let dict = create_test_dict();
+        let queries = GoldenQuery::parse_str("teacher\t老師\nhok\t老師\nlou\t老師 {lou5 si1}\n");
+        let report = evaluate(&dict, &queries, 1, &RankingConfig::default());
+
+        assert_eq!(Some(1), report.queries[0].first_relevant_rank);
+        assert_eq!(1.0, report.queries[0].precision_at_k);
+        assert_eq!(None, report.queries[1].first_relevant_rank);
+        assert_eq!(0.0, report.queries[1].reciprocal_rank);
+        assert_eq!(Some(1), report.queries[2].first_relevant_rank);
+        assert!((report.mrr - 2.0 / 3.0).abs() < 0.001);
+
+        let mut baseline = report.clone();
+        baseline.queries[0].first_relevant_rank = Some(2);
+        baseline.queries[2].first_relevant_rank = None;
+        baseline.queries[1].first_relevant_rank = Some(3);
+
+        let diff = report.diff(&baseline);
+        assert_eq!(1, diff.regressions.len());
+        assert_eq!("hok", diff.regressions[0].query);
+        assert_eq!(2, diff.improvements.len());
+    }
+
+    // Ranking regressions on the test data set fail here.
+    // Re-run `console build test_set eval save_baseline` after an intended ranking change.
+    #[test]
+    fn golden_queries_no_regressions() {
+        let builder = Builder::from_data_dir(TEST_DATA_PATH, &HeuristicsConfig::from_data_dir(TEST_DATA_PATH));
+        let dict = CompiledDictionary::from_builder(builder);
+
+        let queries = GoldenQuery::parse(&format!("{}/golden_queries.tsv", TEST_DATA_PATH));
+        let report = evaluate(&dict, &queries, 5, &RankingConfig::default());
+        let baseline = EvalReport::parse(&format!("{}/golden_baseline.json", TEST_DATA_PATH));
+
+        let diff = report.diff(&baseline);
+        assert!(diff.regressions.is_empty(), "Ranking regressions\n{}\n{}", report, diff);
+        assert!(diff.mrr_delta >= -0.0001, "MRR dropped\n{}\n{}", report, diff);
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::builder::DictionaryEntry;
+use crate::data_reader::DataReader;
+use crate::data_writer::DataWriter;
+
+pub const EXAMPLES_HEADER: &[u8] = b"examples";
+
+// Single characters appear in a huge number of sentences, only keep the first few
+pub const MAX_EXAMPLES_PER_ENTRY: usize = 10;
+
+// Longest word looked up when scanning a sentence
+pub const MAX_LINKED_WORD_CHARS: usize = 8;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[cfg_attr(feature = "tsify", derive(tsify::Tsify))]
+pub struct ExampleSentence {
+    pub chinese: String,
+    pub jyutping: String,
+    pub english: String,
+}
+
+impl ExampleSentence {
+    /// Tab separated, one sentence per line
+    ///     chinese \t jyutping \t english
+    /// Jyutping may be left empty. Empty lines and lines starting with '#' are ignored.
+    pub fn parse_str(data: &str) -> Vec<Self> {
+        let mut sentences = Vec::new();
+        for line in data.lines() {
+            if (line.trim().is_empty() || line.starts_with('#')) {
+                continue;
+            }
+
+            let mut parts = line.split('\t').map(|x| x.trim());
+            let chinese = parts.next().unwrap().to_owned();
+            let jyutping = parts.next().unwrap_or_default().to_owned();
+            let english = parts.next().unwrap_or_else(|| panic!("No english for example '{}'", chinese)).to_owned();
+
+            sentences.push(Self {
+                chinese,
+                jyutping,
+                english,
+            });
+        }
+
+        sentences
+    }
+
+    pub fn parse(path: &str) -> Vec<Self> {
+        let data = std::fs::read_to_string(path).unwrap();
+        let sentences = Self::parse_str(&data);
+        debug_log!("Read {} example sentences from {}", sentences.len(), path);
+        sentences
+    }
+}
+
+/// Link each entry to sentences containing its characters, shortest sentences first.
+/// Sets DictionaryEntry::examples to indexes into sentences.
+pub fn link_examples(entries: &mut [DictionaryEntry], sentences: &[ExampleSentence]) {
+    let mut traditional_to_entries: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (i, e) in entries.iter().enumerate() {
+        if (!e.traditional.is_empty()) {
+            traditional_to_entries.entry(e.traditional.as_str()).or_default().push(i);
+        }
+    }
+
+    let mut links: Vec<Vec<u32>> = vec![Vec::new(); entries.len()];
+
+    let mut order: Vec<usize> = (0..sentences.len()).collect();
+    order.sort_by_key(|i| sentences[*i].chinese.chars().count());
+
+    for sentence_id in order {
+        let chinese = &sentences[sentence_id].chinese;
+        let char_starts: Vec<usize> = chinese.char_indices().map(|(i, _)| i).chain(std::iter::once(chinese.len())).collect();
+        let char_count = char_starts.len() - 1;
+
+        for start in 0..char_count {
+            for len in 1..=MAX_LINKED_WORD_CHARS.min(char_count - start) {
+                let word = &chinese[char_starts[start]..char_starts[start + len]];
+                let Some(entry_ids) = traditional_to_entries.get(word) else {
+                    continue;
+                };
+
+                for &entry_id in entry_ids {
+                    let entry_links = &mut links[entry_id];
+                    if (entry_links.len() < MAX_EXAMPLES_PER_ENTRY && !entry_links.contains(&(sentence_id as u32))) {
+                        entry_links.push(sentence_id as u32);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut linked = 0;
+    for (e, entry_links) in entries.iter_mut().zip(links) {
+        if (!entry_links.is_empty()) {
+            linked += 1;
+        }
+
+        e.examples = entry_links;
+    }
+
+    debug_log!("Linked example sentences to {} of {} entries", linked, entries.len());
+}
+
+/// Example sentences for each entry.
+/// Serialized as its own section so it can be shipped separately from the main dictionary,
+/// the sentence text is deflate compressed.
+#[derive(Debug, Default)]
+pub struct ExampleStore {
+    /// Input hash of the dictionary build these examples were linked against
+    pub input_hash: u64,
+    pub sentences: Vec<ExampleSentence>,
+
+    // Sentences for entry i are entry_sentences[entry_starts[i]..entry_starts[i + 1]]
+    pub entry_starts: Vec<u32>,
+    pub entry_sentences: Vec<u32>,
+}
+
+impl ExampleStore {
+    /// Entries must already be in their final compiled order
+    pub fn from_entries(entries: &[DictionaryEntry], sentences: Vec<ExampleSentence>, input_hash: u64) -> Self {
+        let mut entry_starts = Vec::with_capacity(entries.len() + 1);
+        let mut entry_sentences = Vec::new();
+        for e in entries {
+            entry_starts.push(entry_sentences.len() as u32);
+            entry_sentences.extend_from_slice(&e.examples);
+        }
+        entry_starts.push(entry_sentences.len() as u32);
+
+        Self {
+            input_hash,
+            sentences,
+            entry_starts,
+            entry_sentences,
+        }
+    }
+
+    pub fn entry_count(&self) -> usize {
+        self.entry_starts.len().saturating_sub(1)
+    }
+
+    pub fn examples_for(&self, entry_id: usize, limit: usize) -> Vec<&ExampleSentence> {
+        if (entry_id >= self.entry_count()) {
+            return Vec::new();
+        }
+
+        let start = self.entry_starts[entry_id] as usize;
+        let end = self.entry_starts[entry_id + 1] as usize;
+        self.entry_sentences[start..end].iter().take(limit).map(|x| &self.sentences[*x as usize]).collect()
+    }
+
+    pub fn serialize<T : std::io::Write>(&self, writer: &mut DataWriter<T>) -> std::io::Result<()> {
+        writer.write_bytes(EXAMPLES_HEADER)?;
+        writer.write_u64(self.input_hash)?;
+
+        let mut text = String::new();
+        for s in &self.sentences {
+            text.push_str(&s.chinese);
+            text.push('\t');
+            text.push_str(&s.jyutping);
+            text.push('\t');
+            text.push_str(&s.english);
+            text.push('\n');
+        }
+
+        let compressed = miniz_oxide::deflate::compress_to_vec(text.as_bytes(), 9);
+        debug_log!("Writing {} example sentences, {} bytes compressed to {}", self.sentences.len(), text.len(), compressed.len());
+
+        writer.write_u32(self.sentences.len() as u32)?;
+        writer.write_bytes_and_length(&compressed)?;
+
+        writer.write_u32(self.entry_count() as u32)?;
+        for i in 0..self.entry_count() {
+            let start = self.entry_starts[i] as usize;
+            let end = self.entry_starts[i + 1] as usize;
+            writer.write_vbyte((end - start) as u64)?;
+            for x in &self.entry_sentences[start..end] {
+                writer.write_vbyte(*x as u64)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn deserialize(reader: &mut DataReader) -> Self {
+        let header = reader.read_bytes_len(8);
+        assert!(header == EXAMPLES_HEADER);
+        let input_hash = reader.read_u64();
+
+        let sentence_count = reader.read_u32() as usize;
+        let compressed_size = reader.read_u32() as usize;
+        let compressed = reader.read_bytes_len(compressed_size);
+        let text = miniz_oxide::inflate::decompress_to_vec(compressed).expect("Could not decompress example sentences");
+        let text = std::str::from_utf8(&text).expect("Example sentences not utf8");
+
+        let mut sentences = Vec::with_capacity(sentence_count);
+        for line in text.lines() {
+            let mut parts = line.split('\t');
+            sentences.push(ExampleSentence {
+                chinese: parts.next().unwrap().to_owned(),
+                jyutping: parts.next().unwrap().to_owned(),
+                english: parts.next().unwrap().to_owned(),
+            });
+        }
+        assert_eq!(sentence_count, sentences.len());
+
+        let entry_count = reader.read_u32() as usize;
+        let mut entry_starts = Vec::with_capacity(entry_count + 1);
+        let mut entry_sentences = Vec::new();
+        for _ in 0..entry_count {
+            entry_starts.push(entry_sentences.len() as u32);
+            let count = reader.read_vbyte();
+            for _ in 0..count {
+                entry_sentences.push(reader.read_vbyte() as u32);
+            }
+        }
+        entry_starts.push(entry_sentences.len() as u32);
+
+        Self {
+            input_hash,
+            sentences,
+            entry_starts,
+            entry_sentences,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EntrySource, StringVecSet};
+
+    #[test]
+    fn link_and_roundtrip() {
+        let sentences = ExampleSentence::parse_str(concat!(
+            "# chinese\tjyutping\tenglish\n",
+            "我哋一齊去睇老虎。\tngo5 dei6 jat1 cai4 heoi3 tai2 lou5 fu2.\tLet's go see the tigers together.\n",
+            "老虎\t\tTiger!\n",
+        ));
+        assert_eq!(2, sentences.len());
+
+        let mut entries = vec![
+            DictionaryEntry::new("老虎", "lou5 fu2", EntrySource::CEDict, StringVecSet::single("tiger".to_owned()), 0),
+            DictionaryEntry::new("一齊", "jat1 cai4", EntrySource::CCanto, StringVecSet::single("together".to_owned()), 0),
+            DictionaryEntry::new("獅子", "si1 zi2", EntrySource::CEDict, StringVecSet::single("lion".to_owned()), 0),
+        ];
+
+        link_examples(&mut entries, &sentences);
+
+        // Shortest sentence first
+        assert_eq!(vec![1, 0], entries[0].examples);
+        assert_eq!(vec![0], entries[1].examples);
+        assert!(entries[2].examples.is_empty());
+
+        let store = ExampleStore::from_entries(&entries, sentences, 42);
+
+        let mut writer = DataWriter {
+            write_len: 0,
+            inner: std::io::BufWriter::new(Vec::new()),
+        };
+        store.serialize(&mut writer).unwrap();
+        // End padding for vbyte reads
+        writer.write_u64(0).unwrap();
+        let buffer = writer.inner.into_inner().unwrap();
+        let read = ExampleStore::deserialize(&mut DataReader::new(&buffer));
+
+        assert_eq!(42, read.input_hash);
+        assert_eq!(store.sentences, read.sentences);
+        assert_eq!(vec!["Tiger!"], read.examples_for(0, 1).iter().map(|x| x.english.as_str()).collect::<Vec<_>>());
+        assert_eq!(2, read.examples_for(0, 5).len());
+        assert_eq!("Let's go see the tigers together.", read.examples_for(1, 5)[0].english);
+        assert!(read.examples_for(2, 5).is_empty());
+        assert!(read.examples_for(100, 5).is_empty());
+    }
+}
@@ -0,0 +1,349 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::builder::DictionaryEntry;
+use crate::{EntrySource, JyutpingSplitter};
+
+/// Build time cost adjustments, loaded from a json file so entries can be
+/// demoted without touching the code.
+/// Any field missing from the json falls back to the default below.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeuristicsConfig {
+    // CC-Canto entries without a word frequency only have their character frequencies, these are added on top
+    pub ccanto_base_cost: u32,
+    pub ccanto_per_syllable_cost: u32,
+
+    // No jyutping, probably not a good entry
+    pub missing_jyutping_cost: u32,
+
+    pub rules: Vec<HeuristicRule>,
+    pub overrides: Vec<EntryOverride>,
+}
+
+/// Adds `cost` to every entry matching all of the specified conditions.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeuristicRule {
+    pub name: String,
+    pub cost: u32,
+
+    // Any definition contains any of the terms
+    pub contains: Vec<String>,
+    // No definition contains any of the terms
+    pub does_not_contain: Vec<String>,
+    // Any definition matches the regex
+    pub regex: Option<String>,
+
+    // Only apply to entries from these sources, empty means all
+    pub sources: Vec<EntrySource>,
+
+    pub min_characters: Option<usize>,
+    pub max_characters: Option<usize>,
+}
+
+/// Fix the cost of a specific entry, jyutping can be left out to match all readings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EntryOverride {
+    pub traditional: String,
+    pub jyutping: Option<String>,
+
+    // Replaces the computed cost
+    pub cost: Option<u32>,
+    // Added after the computed or replaced cost
+    pub additional_cost: u32,
+}
+
+impl Default for HeuristicsConfig {
+    fn default() -> Self {
+        Self {
+            ccanto_base_cost: 15_000,
+            ccanto_per_syllable_cost: 1_000,
+            missing_jyutping_cost: 10_000,
+            rules: vec![
+                HeuristicRule::contains("abbr.", &["abbr."], 5_000),
+                HeuristicRule::does_not_contain("no classifier", &["M:", "CL:"], 5_000),
+                HeuristicRule::contains("surname", &["Surname", "surname"], 2_000),
+                HeuristicRule::does_not_contain("not cantonese", &["(Cantonese)"], 2_000),
+                HeuristicRule::contains("confucius", &["Confucius"], 5_000),
+                HeuristicRule::contains("dynasty", &["Dynasty", "Dynasties"], 5_000),
+                HeuristicRule::contains("buddhism", &["(Buddhism)"], 5_000),
+            ],
+            overrides: Vec::new(),
+        }
+    }
+}
+
+impl HeuristicRule {
+    fn contains(name: &str, terms: &[&str], cost: u32) -> Self {
+        Self {
+            name: name.to_owned(),
+            cost,
+            contains: terms.iter().map(|x| x.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn does_not_contain(name: &str, terms: &[&str], cost: u32) -> Self {
+        Self {
+            name: name.to_owned(),
+            cost,
+            does_not_contain: terms.iter().map(|x| x.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl HeuristicsConfig {
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    pub fn parse(path: &str) -> Self {
+        let data = std::fs::read_to_string(path).unwrap();
+        let config = Self::from_json(&data).unwrap_or_else(|e| panic!("Could not parse heuristics {}: {}", path, e));
+        debug_log!("Read {} heuristic rules and {} overrides from {}", config.rules.len(), config.overrides.len(), path);
+        config
+    }
+
+    /// Editable heuristics live next to the source data, fall back to the built in defaults
+    pub fn from_data_dir(data_path: &str) -> Self {
+        let path = format!("{}/heuristics.json", data_path);
+        if std::path::Path::new(&path).exists() {
+            Self::parse(&path)
+        }
+        else {
+            Self::default()
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn apply(&self, entries: &mut [DictionaryEntry]) {
+        let regexes: Vec<Option<Regex>> = self.rules.iter().map(|rule| {
+            rule.regex.as_ref().map(|r| Regex::new(r).unwrap_or_else(|e| panic!("Bad regex in heuristic '{}': {}", rule.name, e)))
+        }).collect();
+
+        for e in entries.iter_mut() {
+            // Costs given by a custom dictionary are kept, only overrides change them
+            if (!e.explicit_cost) {
+                // Entries with a word frequency already have a meaningful cost
+                if (e.has_source(&EntrySource::CCanto) && e.word_frequency.is_none()) {
+                    let jyutping_count = JyutpingSplitter::new(&e.jyutping).count() as u32;
+                    e.cost += self.ccanto_base_cost + jyutping_count * self.ccanto_per_syllable_cost;
+                }
+
+                if (e.jyutping.is_empty()) {
+                    e.cost += self.missing_jyutping_cost;
+                }
+
+                for (rule, regex) in self.rules.iter().zip(regexes.iter()) {
+                    if (rule.matches(e, regex.as_ref())) {
+                        e.cost += rule.cost;
+                    }
+                }
+            }
+
+            for o in &self.overrides {
+                if (o.matches(e)) {
+                    if let Some(cost) = o.cost {
+                        e.cost = cost;
+                    }
+
+                    e.cost += o.additional_cost;
+                }
+            }
+        }
+    }
+}
+
+impl HeuristicRule {
+    fn matches(&self, entry: &DictionaryEntry, regex: Option<&Regex>) -> bool {
+        if (!self.sources.is_empty() && !self.sources.iter().any(|x| entry.has_source(x))) {
+            return false;
+        }
+
+        let character_count = entry.traditional.chars().count();
+        if let Some(min) = self.min_characters && character_count < min {
+            return false;
+        }
+        if let Some(max) = self.max_characters && character_count > max {
+            return false;
+        }
+
+        if (!self.contains.is_empty() && !matches_terms(&self.contains, entry)) {
+            return false;
+        }
+
+        if (!self.does_not_contain.is_empty() && matches_terms(&self.does_not_contain, entry)) {
+            return false;
+        }
+
+        if let Some(r) = regex {
+            if (!entry.definitions().any(|def| r.is_match(def))) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+impl EntryOverride {
+    fn matches(&self, entry: &DictionaryEntry) -> bool {
+        if (self.traditional != entry.traditional) {
+            return false;
+        }
+
+        if let Some(j) = &self.jyutping {
+            return j.eq_ignore_ascii_case(&entry.jyutping);
+        }
+
+        true
+    }
+}
+
+fn matches_terms(needles: &[String], entry: &DictionaryEntry) -> bool {
+    for needle in needles {
+        for heystack in entry.definitions() {
+            if (heystack.contains(needle.as_str())) {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StringVecSet;
+
+    fn entry(traditional: &str, jyutping: &str, definitions: &[&str], source: EntrySource) -> DictionaryEntry {
+        let mut english_sets = StringVecSet::default();
+        for d in definitions {
+            english_sets.add_clone(d);
+        }
+
+        DictionaryEntry::new(traditional, jyutping, source, english_sets, 0)
+    }
+
+    fn empty_config() -> HeuristicsConfig {
+        HeuristicsConfig {
+            ccanto_base_cost: 0,
+            ccanto_per_syllable_cost: 0,
+            missing_jyutping_cost: 0,
+            rules: Vec::new(),
+            overrides: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn default_matches_hardcoded() {
+        let mut entries = vec![
+            entry("老師", "lou5 si1", &["teacher", "CL:個|个[ge4]"], EntrySource::CEDict),
+            entry("嘅", "ge3", &["(Cantonese) possessive particle"], EntrySource::CCanto),
+            entry("孔子", "", &["Confucius", "abbr. for something"], EntrySource::CEDict),
+        ];
+
+        HeuristicsConfig::default().apply(&mut entries);
+
+        // Not cantonese
+        assert_eq!(2_000, entries[0].cost);
+        // Base + 1 syllable + no classifier
+        assert_eq!(15_000 + 1_000 + 5_000, entries[1].cost);
+        // No jyutping + abbr. + no classifier + not cantonese + confucius
+        assert_eq!(10_000 + 5_000 + 5_000 + 2_000 + 5_000, entries[2].cost);
+    }
+
+    #[test]
+    fn rule_conditions() {
+        let mut config = empty_config();
+        config.rules.push(HeuristicRule {
+            name: "long ccanto variants".to_owned(),
+            cost: 100,
+            regex: Some("^variant of".to_owned()),
+            sources: vec![EntrySource::CCanto],
+            min_characters: Some(2),
+            ..Default::default()
+        });
+
+        let mut entries = vec![
+            entry("一並", "jat1 bing6", &["variant of 一併"], EntrySource::CCanto),
+            entry("一並", "jat1 bing6", &["variant of 一併"], EntrySource::CEDict),
+            entry("並", "bing6", &["variant of 併"], EntrySource::CCanto),
+            entry("一並", "jat1 bing6", &["together, variant of 一併"], EntrySource::CCanto),
+        ];
+
+        config.apply(&mut entries);
+
+        assert_eq!(100, entries[0].cost);
+        assert_eq!(0, entries[1].cost);
+        assert_eq!(0, entries[2].cost);
+        assert_eq!(0, entries[3].cost);
+    }
+
+    #[test]
+    fn overrides_from_json() {
+        let config = HeuristicsConfig::from_json(r#"{
+            "rules": [],
+            "overrides": [
+                { "traditional": "虎", "jyutping": "fu2", "cost": 50 },
+                { "traditional": "虎", "additional_cost": 7 }
+            ]
+        }"#).unwrap();
+
+        // Unspecified fields keep defaults
+        assert_eq!(15_000, config.ccanto_base_cost);
+
+        let mut entries = vec![
+            entry("虎", "fu2", &["tiger"], EntrySource::CEDict),
+            entry("虎", "fu1", &["tiger"], EntrySource::CEDict),
+        ];
+
+        entries[1].cost = 1_000;
+
+        config.apply(&mut entries);
+
+        assert_eq!(57, entries[0].cost);
+        assert_eq!(1_007, entries[1].cost);
+    }
+
+    #[test]
+    fn explicit_costs_only_take_overrides() {
+        let mut config = empty_config();
+        config.missing_jyutping_cost = 10_000;
+        config.rules.push(HeuristicRule {
+            name: "legal".to_owned(),
+            cost: 100,
+            regex: Some("^\\(legal\\)".to_owned()),
+            ..Default::default()
+        });
+        config.overrides.push(EntryOverride {
+            traditional: "傳票".to_owned(),
+            additional_cost: 7,
+            ..Default::default()
+        });
+
+        let legal = || EntrySource::Custom("legal".to_owned());
+        let mut entries = vec![
+            entry("傳票", "cyun4 piu3", &["(legal) summons"], legal()),
+            entry("傳票", "cyun4 piu3", &["(legal) summons"], legal()),
+            entry("律師", "", &["(legal) solicitor"], legal()),
+        ];
+
+        entries[0].cost = 500;
+        entries[0].explicit_cost = true;
+
+        config.apply(&mut entries);
+
+        assert_eq!(507, entries[0].cost);
+        // Custom entries without a cost are ranked like any other
+        assert_eq!(107, entries[1].cost);
+        assert_eq!(10_100, entries[2].cost);
+    }
+}
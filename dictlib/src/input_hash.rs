@@ -0,0 +1,65 @@
+/// FNV-1a over everything a build reads, stored in the dictionary header to identify its inputs.
+/// Unlike std's hashers the result doesn't change between platforms or compiler versions.
+#[derive(Debug, Clone, Copy)]
+pub struct InputHasher
+{
+    state: u64,
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+impl Default for InputHasher {
+    fn default() -> Self {
+        Self {
+            state: FNV_OFFSET_BASIS,
+        }
+    }
+}
+
+impl InputHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Length prefixed so consecutive inputs can't run into each other
+    pub fn add(&mut self, bytes: &[u8]) {
+        for b in (bytes.len() as u64).to_le_bytes().iter().chain(bytes) {
+            self.state ^= *b as u64;
+            self.state = self.state.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    /// The file name without its directory and the contents
+    pub fn add_file(&mut self, path: &str) {
+        let name = std::path::Path::new(path).file_name().unwrap().to_string_lossy().into_owned();
+        self.add(name.as_bytes());
+        self.add(&std::fs::read(path).unwrap_or_else(|e| panic!("Could not read {}: {}", path, e)));
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(inputs: &[&str]) -> u64 {
+        let mut hasher = InputHasher::new();
+        for x in inputs {
+            hasher.add(x.as_bytes());
+        }
+        hasher.finish()
+    }
+
+    #[test]
+    fn hash_is_stable() {
+        // Changing this breaks comparing against previously built dictionaries
+        assert_eq!(0xcd941ccd1f958612, hash(&["cedict", "老師"]));
+        assert_eq!(hash(&["ab", "c"]), hash(&["ab", "c"]));
+        assert_ne!(hash(&["ab", "c"]), hash(&["a", "bc"]));
+        assert_ne!(hash(&[]), hash(&[""]));
+    }
+}
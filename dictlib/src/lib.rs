@@ -14,7 +14,7 @@
 use std::time::Instant;
 
 use jyutping_splitter::JyutpingSplitter;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 #[macro_export]
 macro_rules! debug_log {
@@ -38,7 +38,20 @@ pub mod vbyte;
 pub mod string_search;
 pub mod rendered_result;
 pub mod builder;
+pub mod heuristics;
+pub mod eval;
+pub mod tune;
+pub mod dictionary_set;
+pub mod annotations;
+pub mod examples;
+pub mod english_store;
+pub mod entry_codec;
+pub mod stats;
+pub mod input_hash;
+pub mod character_info;
+pub mod words_containing;
 pub mod search;
+pub mod search_session;
 pub mod reconstruct_match;
 
 static mut DEBUG_LOGGER : Option<Box<dyn DebugLogger>> = None;
@@ -137,8 +150,12 @@ impl StringVecSet {
     }
 }
 
-#[derive(Debug, Serialize, PartialEq)]
+// Ordered as merged entries list their sources
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "tsify", derive(tsify::Tsify))]
 pub enum EntrySource {
     CEDict,
     CCanto,
+    // User supplied dictionary, see Builder::parse_custom
+    Custom(String),
 }
@@ -49,31 +49,21 @@ impl CompiledDictionary {
         spans
     }
 
+    /// Spans are offsets into the entry's definitions joined together
     pub fn get_english_matched_spans(&self, entry: &CompiledDictionaryEntry, query: &str) -> Vec<(usize, usize)> {
         let mut spans = Vec::new();
 
-        if entry.english_start == entry.english_end {
-            return spans;
-        }
-
-        //let first_start = self.english_data_starts[entry.english_start as usize] as usize;
-
+        let mut start = 0;
         for def_idx in entry.english_start..entry.english_end {
-            let start = self.english_data_starts[def_idx as usize] as usize;
-            let end = if def_idx + 1 < self.english_data_starts.len() as u32 {
-                self.english_data_starts[def_idx as usize + 1] as usize
-            } else {
-                self.english_data.len()
-            };
-            let def_bytes = &self.english_data[start..end];
+            let definition = self.english.definition(def_idx);
 
             for split in query.split_ascii_whitespace() {
-                if let Some(pos) = crate::string_search::string_indexof_linear_ignorecase(split, def_bytes) {
-                    //let field_idx = 2 + (def_idx - entry.english_start) as usize;
-                    //let pos = pos + first_start;
+                if let Some(pos) = string_indexof_linear_ignorecase(split, definition.as_bytes()) {
                     spans.push((start + pos, start + pos + split.len()));
                 }
             }
+
+            start += definition.len();
         }
 
         spans
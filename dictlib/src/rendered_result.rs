@@ -1,18 +1,67 @@
 use serde::Serialize;
 use crate::EntrySource;
+use crate::annotations::{CrossReferenceKind, EntryAnnotations};
 use crate::compiled_dictionary::*;
 use crate::search::*;
 
 #[derive(Debug, Serialize)]
+#[cfg_attr(feature = "tsify", derive(tsify::Tsify))]
 pub struct RenderedResult {
     pub characters: String,
     pub jyutping: String,
     pub english_definitions: Vec<String>,
     pub cost: u32,
-    pub entry_source: EntrySource,
+    pub entry_sources: Vec<EntrySource>,
+    pub definition_groups: Vec<RenderedDefinitionGroup>,
+    // Any definitions come from a user supplied dictionary
+    pub is_custom: bool,
+    // Classifiers, usage labels (indexing english_definitions) and comments
+    pub annotations: EntryAnnotations,
+    pub cross_references: Vec<RenderedCrossReference>,
+}
+
+/// A link to another entry, or just the referenced characters if they are not in the dictionary
+#[derive(Debug, Serialize, PartialEq)]
+#[cfg_attr(feature = "tsify", derive(tsify::Tsify))]
+pub struct RenderedCrossReference {
+    pub kind: CrossReferenceKind,
+    pub characters: String,
+    pub jyutping: String,
+    pub entry_id: Option<usize>,
+}
+
+/// The definitions from one source, as a [start, end) range into english_definitions
+#[derive(Debug, Serialize, PartialEq)]
+#[cfg_attr(feature = "tsify", derive(tsify::Tsify))]
+pub struct RenderedDefinitionGroup {
+    pub source: EntrySource,
+    pub source_name: String,
+    pub is_custom: bool,
+    pub start: usize,
+    pub end: usize,
 }
 
 impl RenderedResult {
+    /// An entry without any highlighting, eg when following a cross reference
+    pub fn from_entry(entry_id: usize, dict: &CompiledDictionary) -> Self {
+        let unhighlighted = MatchWithHitInfo {
+            match_obj: Match {
+                cost_info: MatchCostInfo {
+                    term_match_cost: 0,
+                    unmatched_position_cost: 0,
+                    inversion_cost: 0,
+                    static_cost: dict.entries[entry_id].cost,
+                },
+                match_type: MatchType::English,
+                entry_id,
+                dictionary_id: 0,
+            },
+            matched_spans: Vec::new(),
+        };
+
+        Self::from_match(&unhighlighted, dict)
+    }
+
     /// Create a rendered result from a match, with hit highlighting applied
     pub fn from_match(match_result: &MatchWithHitInfo, dict: &CompiledDictionary) -> Self {
         let entry = &dict.entries[match_result.match_obj.entry_id];
@@ -76,12 +125,52 @@ impl RenderedResult {
             build_english_definitions_with_highlights(entry, dict, &[])
         };
 
+        let mut definition_groups = Vec::new();
+        let mut entry_sources = Vec::new();
+        for (source, start, end) in entry.get_source_groups(dict) {
+            entry_sources.push(source.clone());
+            definition_groups.push(RenderedDefinitionGroup {
+                source_name: source.display_name().to_owned(),
+                is_custom: source.is_custom(),
+                source,
+                start: (start - entry.english_start) as usize,
+                end: (end - entry.english_start) as usize,
+            });
+        }
+
+        let mut annotations = entry.get_annotations();
+
+        let mut cross_references = Vec::new();
+        for link in dict.get_links(match_result.match_obj.entry_id) {
+            let target = dict.get_display_entry(link.target_entry as usize);
+            cross_references.push(RenderedCrossReference {
+                kind: link.kind,
+                characters: target.characters,
+                jyutping: target.jyutping,
+                entry_id: Some(link.target_entry as usize),
+            });
+        }
+
+        // Unresolved references are rendered the same way, without a target
+        for x in std::mem::take(&mut annotations.cross_references) {
+            cross_references.push(RenderedCrossReference {
+                kind: x.kind,
+                characters: x.characters,
+                jyutping: x.jyutping,
+                entry_id: None,
+            });
+        }
+
         Self {
             characters,
             jyutping,
             english_definitions,
             cost: entry.cost,
-            entry_source: entry.get_source(),
+            is_custom: entry_sources.iter().any(|x| x.is_custom()),
+            annotations,
+            cross_references,
+            entry_sources,
+            definition_groups,
         }
     }
 }
@@ -95,11 +184,12 @@ fn build_english_definitions_with_highlights(
         entry.english_end as usize - entry.english_start as usize
     );
 
+    // Spans are offsets into the definitions joined together
+    let mut end = 0;
     for i in entry.english_start..entry.english_end {
-        let start = dict.english_data_starts[i as usize] as usize;
-        let end = dict.english_data_starts[i as usize + 1] as usize;
-        let blob = &dict.english_data[start..end];
-        let plain_text = unsafe { std::str::from_utf8_unchecked(blob) };
+        let plain_text = dict.english.definition(i);
+        let start = end;
+        end += plain_text.len();
 
         let mut filtered_modified_matches = Vec::with_capacity(matched_spans.len());
         for &(span_start_abs, span_end_abs) in matched_spans {
@@ -116,7 +206,7 @@ fn build_english_definitions_with_highlights(
             filtered_modified_matches.push((span_start_abs - start, span_end_abs - start));
         }
 
-        let highlighted = apply_highlights(plain_text, &filtered_modified_matches);
+        let highlighted = apply_highlights(&plain_text, &filtered_modified_matches);
         english_definitions.push(highlighted);
     }
 
@@ -176,6 +266,7 @@ mod tests {
     use super::*;
     use crate::compiled_dictionary::tests::create_test_dict;
     use crate::search::MatchType;
+    use crate::english_store::EnglishStore;
 
     struct TestStopwatch;
 
@@ -185,6 +276,16 @@ mod tests {
         }
     }
 
+    fn group(source: EntrySource, start: usize, end: usize) -> RenderedDefinitionGroup {
+        RenderedDefinitionGroup {
+            source_name: source.display_name().to_owned(),
+            is_custom: source.is_custom(),
+            source,
+            start,
+            end,
+        }
+    }
+
     #[test]
     fn test_escape_html() {
         assert_eq!(
@@ -480,7 +581,73 @@ mod tests {
         let rendered = RenderedResult::from_match(result, &dict);
 
         // Entry source should be preserved
-        assert_eq!(rendered.entry_source, dict.entries[result.match_obj.entry_id].get_source());
+        assert_eq!(rendered.entry_sources, dict.entries[result.match_obj.entry_id].get_sources(&dict));
+        assert_eq!(vec![group(EntrySource::CEDict, 0, 1)], rendered.definition_groups);
+        assert!(!rendered.is_custom);
+    }
+
+    #[test]
+    fn test_from_match_merged_sources() {
+        let mut dict = create_test_dict();
+
+        // 老師 with a second definition from CC-Canto
+        dict.english = EnglishStore::from_definitions(&["teacher", "sir", "student"]);
+        dict.entries[0].english_end = 2;
+        dict.entries[0].flags = FLAG_SOURCE_CEDICT | FLAG_SOURCE_CCCANTO | FLAG_MERGED_SOURCES;
+        dict.entries[0].source_groups = vec![
+            SourceGroup { source: FLAG_SOURCE_CEDICT, custom_source: 0, definition_count: 1 },
+            SourceGroup { source: FLAG_SOURCE_CCCANTO, custom_source: 0, definition_count: 1 },
+        ];
+        dict.entries[1].english_start = 2;
+        dict.entries[1].english_end = 3;
+
+        let results = dict.search("lou", 8, Box::new(TestStopwatch)).matches;
+        let rendered = RenderedResult::from_match(&results[0], &dict);
+
+        assert_eq!(vec!["teacher", "sir"], rendered.english_definitions);
+        assert_eq!(vec![EntrySource::CEDict, EntrySource::CCanto], rendered.entry_sources);
+        assert_eq!(vec![
+            group(EntrySource::CEDict, 0, 1),
+            group(EntrySource::CCanto, 1, 2),
+        ], rendered.definition_groups);
+    }
+
+    #[test]
+    fn test_from_match_custom_source() {
+        let mut dict = create_test_dict();
+        dict.custom_sources = vec!["legal".to_owned()];
+        dict.entries[0].flags = FLAG_SOURCE_CUSTOM;
+
+        let results = dict.search("lou", 8, Box::new(TestStopwatch)).matches;
+        let rendered = RenderedResult::from_match(&results[0], &dict);
+
+        assert!(rendered.is_custom);
+        assert_eq!(vec![group(EntrySource::Custom("legal".to_owned()), 0, 1)], rendered.definition_groups);
+        assert_eq!("legal", rendered.definition_groups[0].source_name);
+    }
+
+    #[test]
+    fn test_from_match_cross_references() {
+        use crate::annotations::CrossReference;
+        use crate::compiled_dictionary::tests::roundtrip;
+
+        let mut dict = create_test_dict();
+        dict.links = vec![EntryLink { source_entry: 0, target_entry: 1, kind: CrossReferenceKind::SeeAlso }];
+        dict.entries[0].flags |= FLAG_HAS_ANNOTATIONS;
+        dict.entries[0].annotations = Some(Box::new(EntryAnnotations {
+            cross_references: vec![CrossReference { kind: CrossReferenceKind::VariantOf, characters: "老豆".to_owned(), jyutping: String::new() }],
+            ..Default::default()
+        }));
+
+        let dict = roundtrip(&dict);
+        let results = dict.search("lou", 8, Box::new(TestStopwatch)).matches;
+        let rendered = RenderedResult::from_match(&results[0], &dict);
+
+        assert_eq!(vec![
+            RenderedCrossReference { kind: CrossReferenceKind::SeeAlso, characters: "學生".to_owned(), jyutping: "hok6 saang1".to_owned(), entry_id: Some(1) },
+            RenderedCrossReference { kind: CrossReferenceKind::VariantOf, characters: "老豆".to_owned(), jyutping: String::new(), entry_id: None },
+        ], rendered.cross_references);
+        assert!(rendered.annotations.cross_references.is_empty());
     }
 
     #[test]
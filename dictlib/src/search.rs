@@ -1,12 +1,17 @@
 use core::str;
 use std::cell::RefCell;
 
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use bit_set::BitSet;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::Stopwatch;
 
 use crate::compiled_dictionary::*;
+use crate::english_store::EnglishQueryTerm;
 use crate::reconstruct_match::merge_overlapping_match_spans;
 
 pub const OUT_OF_ORDER_INVERSION_PENALTY: u32 = 8_000;
@@ -20,6 +25,69 @@ pub const NON_ASCII_MATCH_IN_ENGLISH_PENALTY: u32 = 8_000;
 pub const ENGLISH_POS_OFFSET_PENALTY_K: u32 = 100;
 pub const ENGLISH_MIDDLE_OF_WORD_PENALTY: u32 = 5_000;
 
+/// Runtime tunable ranking weights.
+/// Defaults match the constants above, any field missing from the json falls back to its default.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RankingConfig {
+    pub out_of_order_inversion_penalty: u32,
+    pub unmatched_jyutping_penalty: u32,
+    pub jyutping_partial_match_penalty_k: u32,
+    pub jyutping_completion_penalty_k: u32,
+    pub jyutping_prefix_levenshtein_penalty_k: u32,
+
+    pub english_base_penalty: u32,
+    pub non_ascii_match_in_english_penalty: u32,
+    pub english_pos_offset_penalty_k: u32,
+    pub english_middle_of_word_penalty: u32,
+}
+
+impl Default for RankingConfig {
+    fn default() -> Self {
+        Self {
+            out_of_order_inversion_penalty: OUT_OF_ORDER_INVERSION_PENALTY,
+            unmatched_jyutping_penalty: UNMATCHED_JYUTPING_PENALTY,
+            jyutping_partial_match_penalty_k: JYUTPING_PARTIAL_MATCH_PENALTY_K,
+            jyutping_completion_penalty_k: JYUTPING_COMPLETION_PENALTY_K,
+            jyutping_prefix_levenshtein_penalty_k: JYUTPING_PREFIX_LEVENSHTEIN_PENALTY_K,
+
+            english_base_penalty: ENGLISH_BASE_PENALTY,
+            non_ascii_match_in_english_penalty: NON_ASCII_MATCH_IN_ENGLISH_PENALTY,
+            english_pos_offset_penalty_k: ENGLISH_POS_OFFSET_PENALTY_K,
+            english_middle_of_word_penalty: ENGLISH_MIDDLE_OF_WORD_PENALTY,
+        }
+    }
+}
+
+impl RankingConfig {
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    pub fn parse(path: &str) -> Self {
+        let data = std::fs::read_to_string(path).unwrap();
+        Self::from_json(&data).unwrap_or_else(|e| panic!("Could not parse ranking config {}: {}", path, e))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn weights_mut(&mut self) -> [(&'static str, &mut u32); 9] {
+        [
+            ("out_of_order_inversion_penalty", &mut self.out_of_order_inversion_penalty),
+            ("unmatched_jyutping_penalty", &mut self.unmatched_jyutping_penalty),
+            ("jyutping_partial_match_penalty_k", &mut self.jyutping_partial_match_penalty_k),
+            ("jyutping_completion_penalty_k", &mut self.jyutping_completion_penalty_k),
+            ("jyutping_prefix_levenshtein_penalty_k", &mut self.jyutping_prefix_levenshtein_penalty_k),
+            ("english_base_penalty", &mut self.english_base_penalty),
+            ("non_ascii_match_in_english_penalty", &mut self.non_ascii_match_in_english_penalty),
+            ("english_pos_offset_penalty_k", &mut self.english_pos_offset_penalty_k),
+            ("english_middle_of_word_penalty", &mut self.english_middle_of_word_penalty),
+        ]
+    }
+}
+
 
 pub struct QueryTerms {
     pub jyutping_terms: Vec<JyutpingQueryTerm>,
@@ -36,6 +104,11 @@ pub struct JyutpingQueryTerm {
 
 impl JyutpingQueryTerm {
     pub fn create(s : &str, jyutping_store: &JyutpingStore) -> Self
+    {
+        Self::create_with_config(s, jyutping_store, &RankingConfig::default())
+    }
+
+    pub fn create_with_config(s : &str, jyutping_store: &JyutpingStore, config: &RankingConfig) -> Self
     {
         debug_assert!(s.len() > 0);
 
@@ -56,8 +129,8 @@ impl JyutpingQueryTerm {
 
             if let Some(idx) = crate::string_search::string_indexof_linear_ignorecase(s, jyutping_string.as_bytes())
             {
-                let mut match_cost = idx as u32 * JYUTPING_PARTIAL_MATCH_PENALTY_K;
-                match_cost += (jyutping_string.len() - s.len()) as u32 * JYUTPING_COMPLETION_PENALTY_K;
+                let mut match_cost = idx as u32 * config.jyutping_partial_match_penalty_k;
+                match_cost += (jyutping_string.len() - s.len()) as u32 * config.jyutping_completion_penalty_k;
                 match_bit_to_match_cost.push((i as i32, match_cost));
                 matches.insert(i);
                 continue;
@@ -66,7 +139,7 @@ impl JyutpingQueryTerm {
             // Warning: Noisy
             let dist = crate::string_search::prefix_levenshtein_ascii(s, jyutping_string);
             if (dist < 2) {
-                let match_cost = dist as u32 * JYUTPING_PREFIX_LEVENSHTEIN_PENALTY_K;
+                let match_cost = dist as u32 * config.jyutping_prefix_levenshtein_penalty_k;
                 match_bit_to_match_cost.push((i as i32, match_cost));
                 matches.insert(i);
                 continue;
@@ -94,6 +167,7 @@ impl JyutpingQueryTerm {
 }
 
 #[derive(Debug, Clone, Copy, Serialize)]
+#[cfg_attr(feature = "tsify", derive(tsify::Tsify))]
 pub struct MatchCostInfo
 {
     pub term_match_cost: u32,
@@ -109,6 +183,7 @@ impl MatchCostInfo {
 }
 
 #[derive(Debug, Clone, Copy, Serialize)]
+#[cfg_attr(feature = "tsify", derive(tsify::Tsify))]
 pub enum MatchType {
     Jyutping,
     Traditional,
@@ -116,20 +191,214 @@ pub enum MatchType {
 }
 
 #[derive(Debug, Clone, Copy, Serialize)]
+#[cfg_attr(feature = "tsify", derive(tsify::Tsify))]
 pub struct Match
 {
     pub cost_info : MatchCostInfo,
     pub match_type: MatchType,
     pub entry_id: usize,
+    // Which dictionary entry_id refers to when searching a DictionarySet, 0 otherwise
+    pub dictionary_id: usize,
+}
+
+impl Match {
+    /// Results are ordered by cost, ties keep dictionary then entry order.
+    /// An entry can match as both english and traditional, english comes first.
+    pub fn sort_key(&self) -> (u32, usize, usize, u8) {
+        let match_type_order = match self.match_type {
+            MatchType::Jyutping => 0,
+            MatchType::English => 1,
+            MatchType::Traditional => 2,
+        };
+
+        (self.cost_info.total(), self.dictionary_id, self.entry_id, match_type_order)
+    }
+}
+
+/// Continues a search after the last result of the previous page.
+/// Keyed on the last result rather than an offset so pages stay consistent and
+/// only the next page needs to be selected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SearchCursor {
+    pub cost: u32,
+    pub dictionary_id: usize,
+    pub entry_id: usize,
+    pub match_type_order: u8,
+}
+
+impl SearchCursor {
+    pub fn after(m: &Match) -> Self {
+        let (cost, dictionary_id, entry_id, match_type_order) = m.sort_key();
+        Self {
+            cost,
+            dictionary_id,
+            entry_id,
+            match_type_order,
+        }
+    }
+
+    pub fn is_before(&self, m: &Match) -> bool {
+        (self.cost, self.dictionary_id, self.entry_id, self.match_type_order) < m.sort_key()
+    }
+
+    /// Opaque string form for passing to and from the web client
+    pub fn to_token(&self) -> String {
+        format!("{}.{}.{}.{}", self.cost, self.dictionary_id, self.entry_id, self.match_type_order)
+    }
+
+    pub fn from_token(token: &str) -> Option<Self> {
+        let mut parts = token.split('.');
+        let cursor = Self {
+            cost: parts.next()?.parse().ok()?,
+            dictionary_id: parts.next()?.parse().ok()?,
+            entry_id: parts.next()?.parse().ok()?,
+            match_type_order: parts.next()?.parse().ok()?,
+        };
+
+        if (parts.next().is_some()) {
+            return None;
+        }
+
+        Some(cursor)
+    }
+}
+
+/// Stops a search from elsewhere, eg another thread when a newer query arrives.
+/// Clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Which page of results to return and when to give up.
+/// A search that runs out of time or is cancelled returns the best matches found so far with truncated set.
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    pub max_results: usize,
+    // The page after this result, None for the first page
+    pub cursor: Option<SearchCursor>,
+
+    // Compared against the search's stopwatch
+    pub time_budget_ms: Option<i32>,
+    pub cancellation: Option<CancellationToken>,
+}
+
+impl SearchOptions {
+    pub fn new(max_results: usize) -> Self {
+        Self {
+            max_results,
+            ..Default::default()
+        }
+    }
+
+    pub fn should_stop(&self, stopwatch: &dyn Stopwatch) -> bool {
+        self.cancellation.as_ref().is_some_and(|x| x.is_cancelled())
+            || self.time_budget_ms.is_some_and(|x| stopwatch.elapsed_ms() >= x)
+    }
+}
+
+// Entries scanned between checks of the time budget and cancellation
+const STOP_CHECK_INTERVAL: usize = 256;
+
+// Orders a BinaryHeap so the worst match is at the top
+#[derive(Debug)]
+struct RankedMatch(Match);
+
+impl PartialEq for RankedMatch {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.sort_key() == other.0.sort_key()
+    }
+}
+
+impl Eq for RankedMatch {}
+
+impl PartialOrd for RankedMatch {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RankedMatch {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.sort_key().cmp(&other.0.sort_key())
+    }
+}
+
+/// The k best matches seen so far, without keeping or sorting the rest
+#[derive(Debug)]
+pub struct TopK {
+    heap: BinaryHeap<RankedMatch>,
+    k: usize,
+    // Every match pushed, including those not kept
+    pub seen: usize,
+}
+
+impl TopK {
+    pub fn new(k: usize) -> Self {
+        Self {
+            heap: BinaryHeap::with_capacity(k + 1),
+            k,
+            seen: 0,
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.heap.len() >= self.k
+    }
+
+    /// Matches costing this or more can't make it in, entries are scanned in order so ties lose
+    pub fn cost_limit(&self) -> u32 {
+        if (!self.is_full()) {
+            return u32::MAX;
+        }
+
+        self.heap.peek().map(|x| x.0.cost_info.total()).unwrap_or(0)
+    }
+
+    pub fn push(&mut self, m: Match) {
+        self.seen += 1;
+        if (self.k == 0) {
+            return;
+        }
+
+        if (self.is_full()) {
+            let worst = self.heap.peek().unwrap();
+            if (m.sort_key() >= worst.0.sort_key()) {
+                return;
+            }
+
+            self.heap.pop();
+        }
+
+        self.heap.push(RankedMatch(m));
+    }
+
+    pub fn into_sorted_vec(self) -> Vec<Match> {
+        self.heap.into_sorted_vec().into_iter().map(|x| x.0).collect()
+    }
 }
 
 #[derive(Debug, Serialize)]
+#[cfg_attr(feature = "tsify", derive(tsify::Tsify))]
 pub struct MatchWithHitInfo {
     pub match_obj: Match,
     pub matched_spans: Vec<(usize, usize)>,
 }
 
 #[derive(Debug, Default, Serialize)]
+#[cfg_attr(feature = "tsify", derive(tsify::Tsify))]
 pub struct Timings {
     pub jyutping_pre_ms: i32,
     pub traditional_pre_ms: i32,
@@ -142,22 +411,83 @@ pub struct Timings {
 pub struct SearchResult {
     pub matches : Vec<MatchWithHitInfo>,
     pub timings: Timings,
+    // Matches found for the query regardless of paging.
+    // The total result count when exhaustive, otherwise a lower bound.
     pub internal_candidates: usize,
+    // False when the scan stopped early, or skipped entries that couldn't make the page
+    // and at least one of them matches after it. internal_candidates is exact only when set.
+    pub exhaustive: bool,
+    // Set when there are more matches after this page, never set when truncated
+    pub next_cursor: Option<SearchCursor>,
+    // The time budget ran out or the search was cancelled before every entry was checked,
+    // matches are the best of those checked. Search again with more time to see the rest.
+    pub truncated: bool,
 }
 
 impl CompiledDictionary {
     pub fn search(&self, s : &str, max_results: usize, stopwatch: Box<dyn Stopwatch>) -> SearchResult
     {
-        let mut result = SearchResult::default();
+        self.search_with_config(s, max_results, &RankingConfig::default(), stopwatch)
+    }
+
+    pub fn search_with_config(&self, s : &str, max_results: usize, config: &RankingConfig, stopwatch: Box<dyn Stopwatch>) -> SearchResult
+    {
+        self.search_with_stopwatch(s, max_results, config, stopwatch.as_ref())
+    }
+
+    // Borrows the stopwatch so it can be shared across a DictionarySet
+    pub fn search_with_stopwatch(&self, s : &str, max_results: usize, config: &RankingConfig, stopwatch: &dyn Stopwatch) -> SearchResult
+    {
+        self.search_page(s, max_results, None, config, stopwatch)
+    }
 
+    /// The page of max_results matches after cursor, None for the first page
+    pub fn search_page(&self, s : &str, max_results: usize, cursor: Option<&SearchCursor>, config: &RankingConfig, stopwatch: &dyn Stopwatch) -> SearchResult
+    {
+        let options = SearchOptions {
+            cursor: cursor.copied(),
+            ..SearchOptions::new(max_results)
+        };
+
+        self.search_with_options(s, &options, config, stopwatch)
+    }
+
+    pub fn search_with_options(&self, s : &str, options: &SearchOptions, config: &RankingConfig, stopwatch: &dyn Stopwatch) -> SearchResult
+    {
+        self.search_in_set(s, options, config, stopwatch, 0)
+    }
+
+    // Matches are tagged with dictionary_id so a cursor can be shared across a DictionarySet
+    pub(crate) fn search_in_set(&self, s : &str, options: &SearchOptions, config: &RankingConfig, stopwatch: &dyn Stopwatch, dictionary_id: usize) -> SearchResult
+    {
         let mut jyutping_query_terms = Vec::new();
         for query_term in s.split_ascii_whitespace()
         {
-            jyutping_query_terms.push(JyutpingQueryTerm::create(query_term, &self.jyutping_store));
+            jyutping_query_terms.push(JyutpingQueryTerm::create_with_config(query_term, &self.jyutping_store, config));
         }
 
-        result.timings.jyutping_pre_ms = stopwatch.elapsed_ms();
+        let jyutping_pre_ms = stopwatch.elapsed_ms();
+
+        let query_terms = QueryTerms {
+            jyutping_terms: jyutping_query_terms,
+            traditional_terms: self.traditional_terms(s),
+        };
+
+        let traditional_pre_ms = stopwatch.elapsed_ms();
+
+        let page = PageRequest {
+            options,
+            dictionary_id,
+        };
 
+        let mut result = self.search_terms(s, &query_terms, &page, config, stopwatch, None);
+        result.timings.jyutping_pre_ms = jyutping_pre_ms;
+        result.timings.traditional_pre_ms = traditional_pre_ms;
+        result
+    }
+
+    pub fn traditional_terms(&self, s : &str) -> Vec<u16>
+    {
         let mut traditional_terms = Vec::new();
         for c in s.chars()
         {
@@ -166,25 +496,88 @@ impl CompiledDictionary {
             }
         }
 
-        result.timings.traditional_pre_ms = stopwatch.elapsed_ms();
+        traditional_terms
+    }
 
-        let query_terms = QueryTerms {
-            jyutping_terms: jyutping_query_terms,
-            traditional_terms,
+    /// Match, rank and highlight with query terms already built.
+    /// With a CandidateScan only its candidates and the entries after them are matched.
+    pub(crate) fn search_terms(&self, s : &str, query_terms: &QueryTerms, page: &PageRequest, config: &RankingConfig, stopwatch: &dyn Stopwatch, mut scan: Option<&mut CandidateScan>) -> SearchResult
+    {
+        let mut result = SearchResult::default();
+
+        let cursor = page.options.cursor.as_ref();
+        let max_results = page.options.max_results;
+        let dictionary_id = page.dictionary_id;
+
+        let mut top = TopK::new(max_results);
+        // Every match including those before the cursor
+        let mut match_count = 0;
+        let mut push = |top: &mut TopK, m: Match| {
+            match_count += 1;
+            if (cursor.is_none_or(|x| x.is_before(&m))) {
+                top.push(m);
+            }
+        };
+
+        // Only candidates from a previous search can match before scan_from
+        let (candidates, scan_from) = match &scan {
+            Some(x) => (x.candidates, x.scan_from),
+            None => (&[][..], 0),
         };
 
-        let mut matches: Vec<Match> = Vec::new();
+        let mut entry_ids = candidates.iter().map(|x| *x as usize).chain(scan_from..self.entries.len());
+
+        // Matched against the english tokens once, entries only look up their tokens
+        let english_terms = if (s.len() > 2) { EnglishQueryTerm::create_all(s, &self.english) } else { Vec::new() };
 
-        for (i, x) in self.entries.iter().enumerate()
+        result.exhaustive = true;
+        let mut scanned_to = self.entries.len();
+        // Entries the scan gave up on without knowing if they match
+        let mut over_limit = Vec::new();
+        let mut skipped_from = None;
+
+        for (checked, i) in entry_ids.by_ref().enumerate()
         {
-            if let Some(mut cost_info) = self.matches_jyutping_term(x, &query_terms)
+            if (checked % STOP_CHECK_INTERVAL == 0 && page.options.should_stop(stopwatch)) {
+                result.truncated = true;
+                result.exhaustive = false;
+                scanned_to = i;
+                break;
+            }
+
+            let x = &self.entries[i];
+
+            let cost_limit = top.cost_limit();
+
+            // Every match costs at least the static cost and entries are sorted by it,
+            // so nothing from here on can beat the current page
+            if (cost_limit != u32::MAX && x.cost >= cost_limit) {
+                result.exhaustive = false;
+                scanned_to = i;
+                skipped_from = Some(i);
+                break;
+            }
+
+            // Recorded candidates must include every match so english can't give up early
+            let english_cost_limit = if (scan.is_some()) { u32::MAX } else { cost_limit };
+
+            let mut matched = false;
+
+            if let Some(mut cost_info) = self.matches_jyutping_term_with_limit(x, query_terms, config, cost_limit)
             {
                 cost_info.static_cost = x.cost;
+                matched = true;
 
-                matches.push(Match {
+                if (cost_limit != u32::MAX && cost_info.total() >= cost_limit) {
+                    // Possibly a lower bound, the remaining costs were skipped
+                    result.exhaustive = false;
+                }
+
+                push(&mut top, Match {
                     cost_info,
                     match_type: MatchType::Jyutping,
                     entry_id: i,
+                    dictionary_id,
                 });
             }
             else
@@ -192,19 +585,30 @@ impl CompiledDictionary {
                 let force_english = false;
                 if (s.len() > 2 || force_english)
                 {
-                    if let Some(cost_info) = self.matches_query_english(x, s)
+                    match self.matches_query_english_with_limit(x, s, &english_terms, config, english_cost_limit)
                     {
-                        matches.push(Match {
-                            cost_info,
-                            match_type: MatchType::English,
-                            entry_id: i,
-                        });
+                        LimitedMatch::Match(cost_info) => {
+                            matched = true;
+
+                            push(&mut top, Match {
+                                cost_info,
+                                match_type: MatchType::English,
+                                entry_id: i,
+                                dictionary_id,
+                            });
+                        }
+                        LimitedMatch::OverLimit => {
+                            // May have been a match that wasn't counted
+                            result.exhaustive = false;
+                            over_limit.push(i);
+                        }
+                        LimitedMatch::NoMatch => {}
                     }
                 }
 
                 if (!query_terms.traditional_terms.is_empty())
                 {
-                    if (self.matches_query_traditional(x, &query_terms)) {
+                    if (self.matches_query_traditional(x, query_terms)) {
                         let cost_info = MatchCostInfo {
                             term_match_cost: 0,
                             unmatched_position_cost: 0,
@@ -212,23 +616,71 @@ impl CompiledDictionary {
                             static_cost: x.cost,
                         };
 
-                        matches.push(Match {
+                        matched = true;
+
+                        push(&mut top, Match {
                             cost_info,
                             match_type: MatchType::Traditional,
                             entry_id: i,
+                            dictionary_id,
                         });
                     }
                 }
             }
+
+            if (matched) {
+                if let Some(scan) = scan.as_mut() {
+                    scan.recorded.push(i as u32);
+                }
+            }
         }
 
-        result.timings.full_match = stopwatch.elapsed_ms();
+        if let Some(scan) = scan.as_mut() {
+            scan.scanned_to = scanned_to;
+        }
 
-        result.internal_candidates = matches.len();
-        debug_log!("Internal candidates: {}", result.internal_candidates);
+        // There are more if we dropped any matches.
+        // Unreached entries of a truncated search could still beat this page, so it can't be continued.
+        let mut has_more = top.seen > max_results;
+        let matches = top.into_sorted_vec();
+
+        // Entries skipped for costing too much can't change the page but may be on the next one,
+        // look for one so the cursor doesn't lead to an empty page
+        if let Some(last) = matches.last().map(SearchCursor::after) && !has_more && !result.exhaustive && !result.truncated {
+            // Not knowing because the time ran out is treated as more
+            has_more = true;
+            let mut stopped = false;
+            let mut found = false;
+
+            for (checked, i) in over_limit.into_iter().chain(skipped_from).chain(entry_ids).enumerate() {
+                if (checked % STOP_CHECK_INTERVAL == 0 && page.options.should_stop(stopwatch)) {
+                    stopped = true;
+                    break;
+                }
 
-        matches.sort_by(|(x), (y)| x.cost_info.total().cmp(&y.cost_info.total()));
-        matches.truncate(max_results);
+                let entry_matches = self.unlimited_matches(i, s, query_terms, &english_terms, config, dictionary_id);
+                match_count += entry_matches.len();
+                if (entry_matches.iter().any(|m| last.is_before(m))) {
+                    found = true;
+                    break;
+                }
+            }
+
+            if (!stopped && !found) {
+                // Every entry that could match has been checked
+                has_more = false;
+                result.exhaustive = true;
+            }
+        }
+
+        if (has_more && !result.truncated) {
+            result.next_cursor = matches.last().map(SearchCursor::after);
+        }
+
+        result.timings.full_match = stopwatch.elapsed_ms();
+
+        result.internal_candidates = match_count;
+        debug_log!("Internal candidates: {}, exhaustive {}, truncated {}", result.internal_candidates, result.exhaustive, result.truncated);
 
         result.timings.rank = stopwatch.elapsed_ms();
 
@@ -237,8 +689,8 @@ impl CompiledDictionary {
         {
             let entry = &self.entries[m.entry_id];
             let mut matched_spans = match m.match_type {
-                MatchType::Jyutping => self.get_jyutping_matched_spans(entry, &query_terms),
-                MatchType::Traditional => self.get_traditional_matched_spans(entry, &query_terms),
+                MatchType::Jyutping => self.get_jyutping_matched_spans(entry, query_terms),
+                MatchType::Traditional => self.get_traditional_matched_spans(entry, query_terms),
                 MatchType::English => self.get_english_matched_spans(entry, s),
             };
 
@@ -254,6 +706,71 @@ impl CompiledDictionary {
 
         result
     }
+
+    /// Every way an entry matches the query, without giving up on costly ones
+    fn unlimited_matches(&self, entry_id: usize, s: &str, query_terms: &QueryTerms, english_terms: &[EnglishQueryTerm], config: &RankingConfig, dictionary_id: usize) -> Vec<Match> {
+        let x = &self.entries[entry_id];
+        let mut matches = Vec::new();
+        let mut add = |cost_info: MatchCostInfo, match_type: MatchType| {
+            matches.push(Match {
+                cost_info,
+                match_type,
+                entry_id,
+                dictionary_id,
+            });
+        };
+
+        if let Some(mut cost_info) = self.matches_jyutping_term_with_limit(x, query_terms, config, u32::MAX) {
+            cost_info.static_cost = x.cost;
+            add(cost_info, MatchType::Jyutping);
+            return matches;
+        }
+
+        let english = if (s.len() > 2) { self.matches_query_english_with_limit(x, s, english_terms, config, u32::MAX) } else { LimitedMatch::NoMatch };
+        if let LimitedMatch::Match(cost_info) = english {
+            add(cost_info, MatchType::English);
+        }
+
+        if (!query_terms.traditional_terms.is_empty() && self.matches_query_traditional(x, query_terms)) {
+            add(MatchCostInfo {
+                term_match_cost: 0,
+                unmatched_position_cost: 0,
+                inversion_cost: 0,
+                static_cost: x.cost,
+            }, MatchType::Traditional);
+        }
+
+        matches
+    }
+}
+
+/// A matcher's result when it can give up on entries that cost too much
+#[derive(Debug)]
+pub enum LimitedMatch {
+    Match(MatchCostInfo),
+    NoMatch,
+    // Stopped at the cost limit before finding out whether the entry matches
+    OverLimit,
+}
+
+/// The options for one dictionary in a set
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PageRequest<'a> {
+    pub options: &'a SearchOptions,
+    pub dictionary_id: usize,
+}
+
+/// Restricts a search to entries that matched a previous, less specific query.
+/// Entries before scan_from only need checking if they are in candidates,
+/// the search records its own matches for the next query.
+#[derive(Debug)]
+pub(crate) struct CandidateScan<'a> {
+    pub candidates: &'a [u32],
+    pub scan_from: usize,
+
+    // Every entry that matched, ascending, and the first entry not looked at
+    pub recorded: Vec<u32>,
+    pub scanned_to: usize,
 }
 
 #[thread_local]
@@ -263,7 +780,13 @@ static mut  s_entry_jyutping_matches : Option<BitSet> = None;
 static mut s_matched_positions : Option<Vec<usize>> = None;
 
 impl CompiledDictionary {
-    pub fn matches_jyutping_term(&self, entry: &CompiledDictionaryEntry, query_terms : &QueryTerms) -> Option<MatchCostInfo> {
+    pub fn matches_jyutping_term(&self, entry: &CompiledDictionaryEntry, query_terms : &QueryTerms, config: &RankingConfig) -> Option<MatchCostInfo> {
+        self.matches_jyutping_term_with_limit(entry, query_terms, config, u32::MAX)
+    }
+
+    /// Once all terms match and the cost is already at cost_limit the remaining costs are skipped,
+    /// the returned cost is then a lower bound that is still at least cost_limit.
+    pub fn matches_jyutping_term_with_limit(&self, entry: &CompiledDictionaryEntry, query_terms : &QueryTerms, config: &RankingConfig, cost_limit: u32) -> Option<MatchCostInfo> {
         // If no jyutping terms in query, this is not a jyutping match
         if query_terms.jyutping_terms.is_empty() {
             return None;
@@ -351,12 +874,21 @@ impl CompiledDictionary {
         //let additional_terms = entry.jyutpings.len() - query_terms.jyutping_matches.len();
         //match_cost += additional_terms as u32 * 10_000;
 
-        let inversion_cost = cost_inversions(&matched_positions);
+        if (entry.cost.saturating_add(total_term_match_cost) >= cost_limit) {
+            return Some(MatchCostInfo {
+                term_match_cost: total_term_match_cost,
+                unmatched_position_cost: 0,
+                inversion_cost: 0,
+                static_cost: 0,
+            });
+        }
+
+        let inversion_cost = cost_inversions(&matched_positions, config.out_of_order_inversion_penalty);
 
         let mut unmatched_position_cost = 0u32;
         for i in 0..entry.jyutping.len() {
             if (!entry_jyutping_matches.contains(i)) {
-                unmatched_position_cost += ((entry.jyutping.len() + 1) - i) as u32 * UNMATCHED_JYUTPING_PENALTY;
+                unmatched_position_cost += ((entry.jyutping.len() + 1) - i) as u32 * config.unmatched_jyutping_penalty;
             }
         }
 
@@ -370,22 +902,27 @@ impl CompiledDictionary {
 }
 
 impl CompiledDictionary {
-    pub fn matches_query_english(&self, entry: &CompiledDictionaryEntry, s : &str) -> Option<MatchCostInfo>
+    pub fn matches_query_english(&self, entry: &CompiledDictionaryEntry, s : &str, config: &RankingConfig) -> Option<MatchCostInfo>
     {
-        // Make sure we prefer jyutping matches
-        let mut match_cost: u32 = ENGLISH_BASE_PENALTY;
+        let english_terms = EnglishQueryTerm::create_all(s, &self.english);
+        match self.matches_query_english_with_limit(entry, s, &english_terms, config, u32::MAX) {
+            LimitedMatch::Match(cost_info) => Some(cost_info),
+            _ => None,
+        }
+    }
 
-        // @Perf do search over enterity instead of individual entries.
+    /// english_terms are the whitespace separated parts of s, see EnglishQueryTerm::create_all.
+    /// Gives up with OverLimit as soon as the cost reaches cost_limit
+    pub fn matches_query_english_with_limit(&self, entry: &CompiledDictionaryEntry, s : &str, english_terms: &[EnglishQueryTerm], config: &RankingConfig, cost_limit: u32) -> LimitedMatch
+    {
+        // Make sure we prefer jyutping matches
+        let mut match_cost: u32 = config.english_base_penalty;
 
         if (entry.english_start == entry.english_end)
         {
-            return None;
+            return LimitedMatch::NoMatch;
         }
 
-        let start = self.english_data_starts[entry.english_start as usize] as usize;
-        let end = self.english_data_starts[entry.english_end as usize] as usize;
-        let block = &self.english_data[start..end];
-
         // We are storing this in a thread_local to try and avoid dynamic
         // allocations as much as possible.
         unsafe {
@@ -397,56 +934,39 @@ impl CompiledDictionary {
         let matched_positions = unsafe { s_matched_positions.as_mut().unwrap() };
         matched_positions.clear();
 
-        for split in s.split_ascii_whitespace()
+        for term in english_terms
         {
-            /*
-            for (i, def) in entry.english_definitions.iter().enumerate()
-            {
-                if let Some(pos) = crate::string_search::string_indexof_linear_ignorecase(split, def) {
-                    match_cost += i as u32 * 1_000;
-                    match_cost += pos as u32 * 100;
-                    continue 'outer;
-                }
-            }
-            */
-
-            // @FIXME entry boundaries etc.
-
-            if let Some(pos) = crate::string_search::string_indexof_linear_ignorecase(split, block) {
-                matched_positions.push(pos);
-                match_cost += pos as u32 * ENGLISH_POS_OFFSET_PENALTY_K;
+            // Positions are into the entry's definitions joined together
+            let Some((pos, word_start)) = self.english.find(entry.english_start..entry.english_end, term) else {
+                // No match on this split
+                return LimitedMatch::NoMatch;
+            };
 
-                if (pos == 0)  {
-                    continue;
-                }
+            matched_positions.push(pos);
+            match_cost += pos as u32 * config.english_pos_offset_penalty_k;
 
-                let start_c = block[pos-1];
-                if (start_c.is_ascii_whitespace() || start_c == b'-')
-                {
-                    continue;
-                }
+            if (entry.cost.saturating_add(match_cost) >= cost_limit) {
+                return LimitedMatch::OverLimit;
+            }
 
+            if (!word_start) {
                 // Match in the middle of a word
-                match_cost += ENGLISH_MIDDLE_OF_WORD_PENALTY;
-                continue;
+                match_cost += config.english_middle_of_word_penalty;
             }
-
-            // No match on this split
-            return None;
         }
 
-        let inversion_cost = cost_inversions(&matched_positions);
+        let inversion_cost = cost_inversions(&matched_positions, config.out_of_order_inversion_penalty);
 
         for c in s.chars() {
             if (!c.is_ascii()) {
                 // Non-ascii match, probably a chinese character
                 // match within an english description
-                match_cost += NON_ASCII_MATCH_IN_ENGLISH_PENALTY;
+                match_cost += config.non_ascii_match_in_english_penalty;
             }
         }
 
 
-        Some(MatchCostInfo {
+        LimitedMatch::Match(MatchCostInfo {
             term_match_cost: match_cost,
             unmatched_position_cost: 0,
             inversion_cost,
@@ -469,13 +989,13 @@ impl CompiledDictionary {
 }
 
 
-pub fn cost_inversions(matched_positions: &[usize]) -> u32
+pub fn cost_inversions(matched_positions: &[usize], inversion_penalty: u32) -> u32
 {
     let mut cost = 0u32;
     for i in 0..matched_positions.len() {
         for j in (i + 1)..matched_positions.len() {
             if matched_positions[i] > matched_positions[j] {
-                cost += OUT_OF_ORDER_INVERSION_PENALTY;
+                cost += inversion_penalty;
             }
         }
     }
@@ -0,0 +1,233 @@
+use crate::compiled_dictionary::CompiledDictionary;
+use crate::search::{CandidateScan, JyutpingQueryTerm, PageRequest, QueryTerms, RankingConfig, SearchCursor, SearchOptions, SearchResult};
+use crate::Stopwatch;
+
+// What the previous query matched in one dictionary
+#[derive(Default)]
+struct DictionaryState {
+    query: String,
+    had_traditional_terms: bool,
+    jyutping_terms: Vec<JyutpingQueryTerm>,
+
+    // Every entry before scanned_to that matched query, ascending
+    candidates: Vec<u32>,
+    scanned_to: usize,
+
+    // Detects the dictionary changing under the session
+    entry_count: usize,
+}
+
+/// Search state kept between keystrokes.
+/// When a query extends the previous one its matches are a subset of the previous matches,
+/// so only those candidates and any entries the previous search didn't reach are matched again.
+/// Jyutping terms that haven't changed are reused. Any other edit falls back to a full search.
+#[derive(Default)]
+pub struct SearchSession {
+    config: Option<RankingConfig>,
+    states: Vec<DictionaryState>,
+
+    // Whether the last search for each dictionary reused the previous candidates
+    pub narrowed: Vec<bool>,
+}
+
+impl SearchSession {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget previous queries, needed when dictionaries are replaced
+    pub fn reset(&mut self) {
+        self.config = None;
+        self.states.clear();
+        self.narrowed.clear();
+    }
+
+    pub fn search(&mut self, dict: &CompiledDictionary, s: &str, max_results: usize, config: &RankingConfig, stopwatch: &dyn Stopwatch) -> SearchResult {
+        self.search_page(dict, s, max_results, None, config, stopwatch)
+    }
+
+    /// As CompiledDictionary::search_page
+    pub fn search_page(&mut self, dict: &CompiledDictionary, s: &str, max_results: usize, cursor: Option<&SearchCursor>, config: &RankingConfig, stopwatch: &dyn Stopwatch) -> SearchResult {
+        let options = SearchOptions {
+            cursor: cursor.copied(),
+            ..SearchOptions::new(max_results)
+        };
+
+        self.search_with_options(dict, s, &options, config, stopwatch)
+    }
+
+    /// As CompiledDictionary::search_with_options.
+    /// A truncated search still leaves the session able to narrow the next query.
+    pub fn search_with_options(&mut self, dict: &CompiledDictionary, s: &str, options: &SearchOptions, config: &RankingConfig, stopwatch: &dyn Stopwatch) -> SearchResult {
+        let page = PageRequest {
+            options,
+            dictionary_id: 0,
+        };
+
+        self.search_dictionary(dict, s, &page, config, stopwatch)
+    }
+
+    pub(crate) fn search_dictionary(&mut self, dict: &CompiledDictionary, s: &str, page: &PageRequest, config: &RankingConfig, stopwatch: &dyn Stopwatch) -> SearchResult {
+        if (self.config.as_ref() != Some(config)) {
+            // Cached terms and candidates depend on the ranking weights
+            self.reset();
+            self.config = Some(config.clone());
+        }
+
+        let dictionary_id = page.dictionary_id;
+        if (self.states.len() <= dictionary_id) {
+            self.states.resize_with(dictionary_id + 1, Default::default);
+            self.narrowed.resize(dictionary_id + 1, false);
+        }
+
+        let state = &mut self.states[dictionary_id];
+        if (state.entry_count != dict.entries.len()) {
+            *state = DictionaryState {
+                entry_count: dict.entries.len(),
+                ..Default::default()
+            };
+        }
+
+        let mut jyutping_terms = Vec::new();
+        for query_term in s.split_ascii_whitespace() {
+            let previous = state.jyutping_terms.iter().position(|x| x.string_with_tone() == query_term);
+            let term = match previous {
+                Some(i) => state.jyutping_terms.swap_remove(i),
+                None => JyutpingQueryTerm::create_with_config(query_term, &dict.jyutping_store, config),
+            };
+
+            jyutping_terms.push(term);
+        }
+
+        let jyutping_pre_ms = stopwatch.elapsed_ms();
+
+        let query_terms = QueryTerms {
+            jyutping_terms,
+            traditional_terms: dict.traditional_terms(s),
+        };
+
+        let traditional_pre_ms = stopwatch.elapsed_ms();
+
+        // Extending a query only adds constraints, except that english matching needs three characters
+        // and the first character search term adds matches by itself
+        let narrow = !state.query.is_empty()
+            && s.starts_with(&state.query)
+            && (s == state.query || (state.query.len() > 2 && (state.had_traditional_terms || query_terms.traditional_terms.is_empty())));
+
+        let mut scan = CandidateScan {
+            candidates: if (narrow) { &state.candidates } else { &[] },
+            scan_from: if (narrow) { state.scanned_to } else { 0 },
+            recorded: Vec::new(),
+            scanned_to: 0,
+        };
+
+        let mut result = dict.search_terms(s, &query_terms, page, config, stopwatch, Some(&mut scan));
+        result.timings.jyutping_pre_ms = jyutping_pre_ms;
+        result.timings.traditional_pre_ms = traditional_pre_ms;
+
+        let CandidateScan { recorded, scanned_to, .. } = scan;
+        state.candidates = recorded;
+        state.scanned_to = scanned_to;
+        state.query = s.to_owned();
+        state.had_traditional_terms = !query_terms.traditional_terms.is_empty();
+        state.jyutping_terms = query_terms.jyutping_terms;
+
+        self.narrowed[dictionary_id] = narrow;
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{Builder, DictionaryEntry};
+    use crate::{EntrySource, StringVecSet};
+
+    struct TestStopwatch;
+
+    impl Stopwatch for TestStopwatch {
+        fn elapsed_ms(&self) -> i32 {
+            0
+        }
+    }
+
+    fn create_dict() -> CompiledDictionary {
+        let mut builder = Builder::default();
+        for (traditional, jyutping, english, cost) in [
+            ("老師", "lou5 si1", "teacher", 1_000),
+            ("老鼠", "lou5 syu2", "mouse; rat", 1_200),
+            ("路", "lou6", "road; path", 500),
+            ("老", "lou5", "old; always", 600),
+            ("佬", "lou2", "guy; fellow", 2_000),
+            ("學生", "hok6 saang1", "student; pupil", 800),
+            ("學", "hok6", "to learn; to study", 300),
+            ("生", "saang1", "to be born; raw", 400),
+            ("師", "si1", "teacher; master", 700),
+            ("茶", "caa4", "tea", 900),
+            ("教", "gaau3", "to teach", 1_100),
+        ] {
+            builder.entries.push(DictionaryEntry::new(traditional, jyutping, EntrySource::CEDict, StringVecSet::single(english.to_owned()), cost));
+        }
+
+        CompiledDictionary::from_builder(builder)
+    }
+
+    fn ids(result: &SearchResult) -> Vec<(usize, u32)> {
+        result.matches.iter().map(|x| (x.match_obj.entry_id, x.match_obj.cost_info.total())).collect()
+    }
+
+    #[test]
+    fn typing_matches_full_search() {
+        let dict = create_dict();
+        let config = RankingConfig::default();
+
+        let keystrokes = [
+            "t", "te", "tea", "teac", "teach", "teache", "teacher",
+            // Edit, not an extension
+            "teach", "l", "lo", "lou", "lou ", "lou s", "lou si", "lou si1",
+            "lou si1老", "s", "st", "stu", "學", "學生", "h", "ho", "hok", "hok6 saang",
+        ];
+
+        for max_results in [1, 2, 5, 20] {
+            let mut session = SearchSession::new();
+            for s in keystrokes {
+                let expected = dict.search_with_config(s, max_results, &config, Box::new(TestStopwatch));
+                let result = session.search(&dict, s, max_results, &config, &TestStopwatch);
+                assert_eq!(ids(&expected), ids(&result), "query '{}' max_results {}", s, max_results);
+            }
+        }
+    }
+
+    #[test]
+    fn narrows_only_on_extension() {
+        let dict = create_dict();
+        let config = RankingConfig::default();
+        let mut session = SearchSession::new();
+
+        session.search(&dict, "tea", 5, &config, &TestStopwatch);
+        assert_eq!(vec![false], session.narrowed);
+
+        session.search(&dict, "teac", 5, &config, &TestStopwatch);
+        assert_eq!(vec![true], session.narrowed);
+
+        session.search(&dict, "tex", 5, &config, &TestStopwatch);
+        assert_eq!(vec![false], session.narrowed);
+
+        // Too short to have matched english
+        session.search(&dict, "l", 5, &config, &TestStopwatch);
+        session.search(&dict, "lou", 5, &config, &TestStopwatch);
+        assert_eq!(vec![false], session.narrowed);
+
+        // Characters match by themselves so adding the first one can't narrow
+        session.search(&dict, "lou老", 5, &config, &TestStopwatch);
+        assert_eq!(vec![false], session.narrowed);
+
+        // Changing weights starts again
+        let other_config = RankingConfig { english_base_penalty: 0, ..Default::default() };
+        session.search(&dict, "lou老師", 5, &other_config, &TestStopwatch);
+        assert_eq!(vec![false], session.narrowed);
+        session.search(&dict, "lou老師", 5, &other_config, &TestStopwatch);
+        assert_eq!(vec![true], session.narrowed);
+    }
+}
@@ -0,0 +1,258 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::compiled_dictionary::CompiledDictionary;
+use crate::data_writer::DataWriter;
+
+// Entries listed in DictionaryStats::longest_entries
+pub const LONGEST_ENTRY_COUNT: usize = 10;
+
+/// Sizes of each part of a written dictionary file and a summary of its entries.
+/// Returned by CompiledDictionary::serialize, or CompiledDictionary::stats for a loaded dictionary.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DictionaryStats
+{
+    // In the order they are written
+    pub sections: Vec<SectionSize>,
+    pub total_bytes: usize,
+
+    // See Builder::input_hash
+    pub input_hash: u64,
+
+    pub entry_count: usize,
+    pub definition_count: usize,
+    pub link_count: usize,
+
+    // Entries with merged sources count once for each
+    pub entries_per_source: Vec<SourceCount>,
+
+    // Indexed by the number of jyutping syllables
+    pub entries_by_syllable_count: Vec<usize>,
+
+    // Power of two buckets of static cost, only those with entries
+    pub cost_histogram: Vec<CostBucket>,
+
+    // Most characters first
+    pub longest_entries: Vec<LongestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SectionSize
+{
+    pub name: String,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SourceCount
+{
+    pub source: String,
+    pub entries: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CostBucket
+{
+    // Inclusive
+    pub min_cost: u32,
+    pub max_cost: u32,
+    pub entries: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LongestEntry
+{
+    pub entry_id: usize,
+    pub characters: String,
+    pub jyutping: String,
+}
+
+fn cost_bucket(cost: u32) -> usize {
+    (u32::BITS - cost.leading_zeros()) as usize
+}
+
+impl DictionaryStats {
+    /// Summary of the entries in range
+    pub fn from_entries(dict: &CompiledDictionary, range: std::ops::Range<usize>) -> Self {
+        let entries = &dict.entries[range.clone()];
+
+        let mut stats = Self {
+            input_hash: dict.input_hash,
+            entry_count: entries.len(),
+            ..Default::default()
+        };
+
+        let mut cost_counts = vec![0; cost_bucket(u32::MAX) + 1];
+        for e in entries {
+            stats.definition_count += (e.english_end - e.english_start) as usize;
+
+            for source in e.get_sources(dict) {
+                let name = source.display_name();
+                match stats.entries_per_source.iter_mut().find(|x| x.source == name) {
+                    Some(x) => x.entries += 1,
+                    None => stats.entries_per_source.push(SourceCount { source: name.to_owned(), entries: 1 }),
+                }
+            }
+
+            if (stats.entries_by_syllable_count.len() <= e.jyutping.len()) {
+                stats.entries_by_syllable_count.resize(e.jyutping.len() + 1, 0);
+            }
+            stats.entries_by_syllable_count[e.jyutping.len()] += 1;
+
+            cost_counts[cost_bucket(e.cost)] += 1;
+        }
+
+        // Stable so ties keep the cheapest entry
+        let mut longest : Vec<usize> = range.collect();
+        longest.sort_by_key(|x| std::cmp::Reverse(dict.entries[*x].characters.len()));
+        for entry_id in longest.into_iter().take(LONGEST_ENTRY_COUNT) {
+            let display = dict.get_display_entry(entry_id);
+            stats.longest_entries.push(LongestEntry {
+                entry_id,
+                characters: display.characters,
+                jyutping: display.jyutping,
+            });
+        }
+
+        for (bucket, entries) in cost_counts.into_iter().enumerate() {
+            if (entries > 0) {
+                stats.cost_histogram.push(CostBucket {
+                    min_cost: if (bucket == 0) { 0 } else { 1 << (bucket - 1) },
+                    max_cost: if (bucket == 0) { 0 } else { ((1u64 << bucket) - 1) as u32 },
+                    entries,
+                });
+            }
+        }
+
+        stats
+    }
+
+    /// Adds to the section called name, creating it if needed
+    pub fn add_section(&mut self, name: &str, bytes: usize) {
+        match self.sections.iter_mut().find(|x| x.name == name) {
+            Some(x) => x.bytes += bytes,
+            None => self.sections.push(SectionSize { name: name.to_owned(), bytes }),
+        }
+    }
+
+    /// Runs write, recording what it wrote as the section called name
+    pub fn measure<T : std::io::Write>(&mut self, name: &str, writer: &mut DataWriter<T>, write: impl FnOnce(&mut DataWriter<T>) -> std::io::Result<()>) -> std::io::Result<()> {
+        let start = writer.write_len;
+        write(writer)?;
+        self.add_section(name, writer.write_len - start);
+        Ok(())
+    }
+
+    pub fn section_bytes(&self, name: &str) -> Option<usize> {
+        self.sections.iter().find(|x| x.name == name).map(|x| x.bytes)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+impl fmt::Display for DictionaryStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<24} {:>12} {:>8}", "section", "bytes", "%")?;
+        for x in &self.sections {
+            let percent = 100.0 * x.bytes as f64 / self.total_bytes.max(1) as f64;
+            writeln!(f, "{:<24} {:>12} {:>8.2}", x.name, x.bytes, percent)?;
+        }
+        writeln!(f, "{:<24} {:>12}", "total", self.total_bytes)?;
+
+        writeln!(f)?;
+        writeln!(f, "input hash {:016x}", self.input_hash)?;
+        writeln!(f, "{} entries, {} definitions, {} links", self.entry_count, self.definition_count, self.link_count)?;
+
+        writeln!(f)?;
+        writeln!(f, "{:<24} {:>12}", "source", "entries")?;
+        for x in &self.entries_per_source {
+            writeln!(f, "{:<24} {:>12}", x.source, x.entries)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "{:<24} {:>12}", "syllables", "entries")?;
+        for (syllables, entries) in self.entries_by_syllable_count.iter().enumerate() {
+            if (*entries > 0) {
+                writeln!(f, "{:<24} {:>12}", syllables, entries)?;
+            }
+        }
+
+        writeln!(f)?;
+        writeln!(f, "{:<24} {:>12}", "cost", "entries")?;
+        for x in &self.cost_histogram {
+            writeln!(f, "{:<24} {:>12}", format!("{}-{}", x.min_cost, x.max_cost), x.entries)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "longest entries")?;
+        for x in &self.longest_entries {
+            writeln!(f, "{:>8} {} ({})", x.entry_id, x.characters, x.jyutping)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl CompiledDictionary {
+    /// Stats for the file serialize would write, without writing it.
+    /// For a loaded dictionary that is the file it was read from.
+    pub fn stats(&self) -> DictionaryStats {
+        let mut writer = DataWriter {
+            write_len: 0,
+            inner: std::io::BufWriter::new(std::io::sink()),
+        };
+
+        self.serialize(&mut writer).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiled_dictionary::tests::create_test_dict;
+
+    #[test]
+    fn stats_match_written_file() {
+        let dict = create_test_dict();
+
+        let mut writer = DataWriter {
+            write_len: 0,
+            inner: std::io::BufWriter::new(Vec::new()),
+        };
+        let stats = dict.serialize(&mut writer).unwrap();
+        let buffer = writer.inner.into_inner().unwrap();
+
+        assert_eq!(buffer.len(), stats.total_bytes);
+        assert_eq!(buffer.len(), stats.sections.iter().map(|x| x.bytes).sum::<usize>());
+        assert_eq!(stats.total_bytes, dict.stats().total_bytes);
+
+        assert_eq!(2, stats.entry_count);
+        assert_eq!(2, stats.definition_count);
+        assert_eq!(vec![SourceCount { source: "CEDict".to_owned(), entries: 2 }], stats.entries_per_source);
+        assert_eq!(vec![0, 0, 2], stats.entries_by_syllable_count);
+        assert_eq!(vec![CostBucket { min_cost: 64, max_cost: 127, entries: 2 }], stats.cost_histogram);
+        assert_eq!("老師", stats.longest_entries[0].characters);
+        assert_eq!("lou5 si1", stats.longest_entries[0].jyutping);
+    }
+
+    #[test]
+    fn stats_match_loaded_core() {
+        let dict = create_test_dict();
+
+        let mut writer = DataWriter {
+            write_len: 0,
+            inner: std::io::BufWriter::new(Vec::new()),
+        };
+        let written = dict.serialize_core(&mut writer, 1).unwrap();
+        let buffer = writer.inner.into_inner().unwrap();
+
+        // The core's entry codec covers entries it doesn't have
+        let core = CompiledDictionary::deserialize(&mut crate::data_reader::DataReader::new(&buffer));
+        let stats = core.stats();
+        assert_eq!(buffer.len(), stats.total_bytes);
+        assert_eq!(written.sections, stats.sections);
+    }
+}
@@ -0,0 +1,252 @@
+use serde::{Deserialize, Serialize};
+
+use crate::builder::{Builder, DictionaryEntry};
+use crate::compiled_dictionary::CompiledDictionary;
+use crate::eval::{evaluate, GoldenQuery};
+use crate::heuristics::HeuristicsConfig;
+use crate::search::RankingConfig;
+
+// Multipliers tried against the current value of each weight
+pub const TUNE_STEP_FACTORS: &[f32] = &[0.0, 0.25, 0.5, 0.8, 1.25, 2.0, 4.0];
+
+// Multiplying zero gets us nowhere, so zeroed weights try these instead
+pub const TUNE_FROM_ZERO_VALUES: &[u32] = &[1_000, 5_000, 20_000];
+
+/// Search and build weights together, the output of `console tune`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TunedWeights {
+    pub ranking: RankingConfig,
+    pub heuristics: HeuristicsConfig,
+}
+
+impl TunedWeights {
+    pub fn parse(path: &str) -> Self {
+        let data = std::fs::read_to_string(path).unwrap();
+        serde_json::from_str(&data).unwrap_or_else(|e| panic!("Could not parse tuned weights {}: {}", path, e))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    fn all_weights(&self) -> Vec<Weight> {
+        let mut weights = Vec::new();
+        for i in 0..RankingConfig::default().weights_mut().len() {
+            weights.push(Weight::Ranking(i));
+        }
+
+        weights.push(Weight::CCantoBase);
+        weights.push(Weight::CCantoPerSyllable);
+        weights.push(Weight::MissingJyutping);
+
+        for i in 0..self.heuristics.rules.len() {
+            weights.push(Weight::Rule(i));
+        }
+
+        weights
+    }
+
+    fn weight_mut(&mut self, weight: Weight) -> (String, &mut u32) {
+        match weight {
+            Weight::Ranking(i) => {
+                let (name, value) = self.ranking.weights_mut().into_iter().nth(i).unwrap();
+                (format!("ranking.{}", name), value)
+            },
+            Weight::CCantoBase => ("heuristics.ccanto_base_cost".to_owned(), &mut self.heuristics.ccanto_base_cost),
+            Weight::CCantoPerSyllable => ("heuristics.ccanto_per_syllable_cost".to_owned(), &mut self.heuristics.ccanto_per_syllable_cost),
+            Weight::MissingJyutping => ("heuristics.missing_jyutping_cost".to_owned(), &mut self.heuristics.missing_jyutping_cost),
+            Weight::Rule(i) => {
+                let rule = &mut self.heuristics.rules[i];
+                (format!("heuristics.rules[{}]", rule.name), &mut rule.cost)
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Weight {
+    Ranking(usize),
+    CCantoBase,
+    CCantoPerSyllable,
+    MissingJyutping,
+    Rule(usize),
+}
+
+impl Weight {
+    // Heuristic costs are baked into entries so changing them means recompiling
+    fn needs_rebuild(&self) -> bool {
+        !matches!(self, Weight::Ranking(_))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TuneStep {
+    pub weight: String,
+    pub old_value: u32,
+    pub new_value: u32,
+    pub mrr: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct TuneResult {
+    pub weights: TunedWeights,
+    pub initial_mrr: f32,
+    pub mrr: f32,
+    pub steps: Vec<TuneStep>,
+    pub evaluations: usize,
+}
+
+/// Coordinate descent over all weights, maximising MRR on a labelled query set.
+/// Each round tries a handful of values for every weight in turn and keeps any that improve the score.
+pub struct Tuner<'a> {
+    // Parsed entries before any heuristics are applied
+    raw_entries: &'a [DictionaryEntry],
+    queries: &'a [GoldenQuery],
+    k: usize,
+    evaluations: usize,
+}
+
+impl<'a> Tuner<'a> {
+    pub fn new(raw_entries: &'a [DictionaryEntry], queries: &'a [GoldenQuery], k: usize) -> Self {
+        Self {
+            raw_entries,
+            queries,
+            k,
+            evaluations: 0,
+        }
+    }
+
+    fn build(&self, heuristics: &HeuristicsConfig) -> CompiledDictionary {
+        let mut builder = Builder {
+            entries: self.raw_entries.to_vec(),
+            ..Default::default()
+        };
+
+        builder.finish(heuristics);
+        CompiledDictionary::from_builder(builder)
+    }
+
+    fn score(&mut self, dict: &CompiledDictionary, ranking: &RankingConfig) -> f32 {
+        self.evaluations += 1;
+        evaluate(dict, self.queries, self.k, ranking).mrr
+    }
+
+    pub fn tune(&mut self, start: TunedWeights, max_rounds: usize) -> TuneResult {
+        let mut current = start;
+        let mut dict = self.build(&current.heuristics);
+        let initial_mrr = self.score(&dict, &current.ranking);
+        let mut best_mrr = initial_mrr;
+        let mut steps = Vec::new();
+
+        for _ in 0..max_rounds {
+            let mut improved = false;
+
+            for weight in current.all_weights() {
+                let old_value = *current.weight_mut(weight).1;
+                let mut best_candidate: Option<(u32, f32)> = None;
+
+                for value in candidate_values(old_value) {
+                    let mut candidate = current.clone();
+                    *candidate.weight_mut(weight).1 = value;
+
+                    let mrr = if weight.needs_rebuild() {
+                        let candidate_dict = self.build(&candidate.heuristics);
+                        self.score(&candidate_dict, &candidate.ranking)
+                    }
+                    else {
+                        self.score(&dict, &candidate.ranking)
+                    };
+
+                    let to_beat = best_candidate.map(|(_, x)| x).unwrap_or(best_mrr);
+                    if (mrr > to_beat + f32::EPSILON) {
+                        best_candidate = Some((value, mrr));
+                    }
+                }
+
+                if let Some((new_value, mrr)) = best_candidate {
+                    let (name, value) = current.weight_mut(weight);
+                    *value = new_value;
+
+                    steps.push(TuneStep {
+                        weight: name,
+                        old_value,
+                        new_value,
+                        mrr,
+                    });
+
+                    if (weight.needs_rebuild()) {
+                        dict = self.build(&current.heuristics);
+                    }
+
+                    best_mrr = mrr;
+                    improved = true;
+                }
+            }
+
+            if (!improved) {
+                break;
+            }
+        }
+
+        TuneResult {
+            weights: current,
+            initial_mrr,
+            mrr: best_mrr,
+            steps,
+            evaluations: self.evaluations,
+        }
+    }
+}
+
+fn candidate_values(value: u32) -> Vec<u32> {
+    if (value == 0) {
+        return TUNE_FROM_ZERO_VALUES.to_vec();
+    }
+
+    let mut values = Vec::with_capacity(TUNE_STEP_FACTORS.len());
+    for factor in TUNE_STEP_FACTORS {
+        let x = (value as f32 * factor).round() as u32;
+        if (x != value && !values.contains(&x)) {
+            values.push(x);
+        }
+    }
+
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EntrySource, StringVecSet};
+
+    fn entry(traditional: &str, jyutping: &str, definition: &str, source: EntrySource) -> DictionaryEntry {
+        DictionaryEntry::new(traditional, jyutping, source, StringVecSet::single(definition.to_owned()), 0)
+    }
+
+    #[test]
+    fn tune_ccanto_base_cost() {
+        let raw_entries = vec![
+            entry("胡", "wu4", "surname Hu", EntrySource::CEDict),
+            entry("湖", "wu4", "(Cantonese) lake", EntrySource::CCanto),
+        ];
+
+        // With the default heuristics the CC-Canto entry is pushed below the surname
+        let queries = GoldenQuery::parse_str("wu\t湖\n");
+        let mut tuner = Tuner::new(&raw_entries, &queries, 1);
+        let result = tuner.tune(TunedWeights::default(), 3);
+
+        assert_eq!(0.5, result.initial_mrr);
+        assert_eq!(1.0, result.mrr);
+        assert!(result.steps.iter().any(|x| x.weight.starts_with("heuristics.")));
+
+        let roundtrip: TunedWeights = serde_json::from_str(&result.weights.to_json()).unwrap();
+        assert_eq!(result.weights.ranking, roundtrip.ranking);
+    }
+
+    #[test]
+    fn candidates() {
+        assert_eq!(TUNE_FROM_ZERO_VALUES.to_vec(), candidate_values(0));
+        assert_eq!(vec![0, 25, 50, 80, 125, 200, 400], candidate_values(100));
+    }
+}
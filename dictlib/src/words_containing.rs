@@ -0,0 +1,191 @@
+use serde::Deserialize;
+
+use crate::compiled_dictionary::CompiledDictionary;
+use crate::search::{Match, MatchCostInfo, MatchType, MatchWithHitInfo};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[cfg_attr(feature = "tsify", derive(tsify::Tsify))]
+pub enum CharacterPosition {
+    #[default]
+    Anywhere,
+    Start,
+    End,
+}
+
+/// Words containing some characters, eg all words starting with 食
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[cfg_attr(feature = "tsify", derive(tsify::Tsify))]
+#[serde(default)]
+pub struct WordsContainingQuery {
+    pub characters: String,
+    pub position: CharacterPosition,
+
+    // Word length in characters, inclusive
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+}
+
+impl WordsContainingQuery {
+    /// Wildcard form typed into the search box
+    ///     食*  starts with 食
+    ///     *食  ends with 食
+    ///     *食* contains 食 anywhere
+    /// None if there is no wildcard or nothing to search for.
+    pub fn parse_pattern(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let starts_wild = s.starts_with('*');
+        let ends_wild = s.ends_with('*');
+
+        let characters = s.trim_matches('*');
+        if (characters.is_empty() || characters.contains('*') || characters.contains(char::is_whitespace)) {
+            return None;
+        }
+
+        let position = match (starts_wild, ends_wild) {
+            (false, false) => return None,
+            (true, true) => CharacterPosition::Anywhere,
+            (false, true) => CharacterPosition::Start,
+            (true, false) => CharacterPosition::End,
+        };
+
+        Some(Self {
+            characters: characters.to_owned(),
+            position,
+            ..Default::default()
+        })
+    }
+
+    /// Character offset of the match within word, if it matches
+    fn find(&self, word: &[u16], query: &[u16]) -> Option<usize> {
+        if (self.min_length.is_some_and(|x| word.len() < x) || self.max_length.is_some_and(|x| word.len() > x)) {
+            return None;
+        }
+
+        match self.position {
+            CharacterPosition::Start => word.starts_with(query).then_some(0),
+            CharacterPosition::End => word.ends_with(query).then(|| word.len() - query.len()),
+            CharacterPosition::Anywhere => word.windows(query.len()).position(|x| x == query),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct WordsContainingResult {
+    pub matches: Vec<MatchWithHitInfo>,
+    // Number of matching entries ignoring offset and limit
+    pub total: usize,
+}
+
+impl CompiledDictionary {
+    /// Matching entries ordered by cost, skipping the first offset.
+    /// Matches are Traditional with the matched characters as the span so they render like search results.
+    pub fn words_containing(&self, query: &WordsContainingQuery, offset: usize, limit: usize) -> WordsContainingResult {
+        let mut result = WordsContainingResult::default();
+
+        let mut query_ids = Vec::new();
+        for c in query.characters.chars() {
+            match self.character_store.char_to_index(c) {
+                Some(x) => query_ids.push(x),
+                // No entry can contain a character we don't have
+                None => return result,
+            }
+        }
+
+        if (query_ids.is_empty()) {
+            return result;
+        }
+
+        // Entries are already sorted by cost
+        for (entry_id, entry) in self.entries.iter().enumerate() {
+            let Some(start) = query.find(&entry.characters, &query_ids) else {
+                continue;
+            };
+
+            if (result.total >= offset && result.matches.len() < limit) {
+                result.matches.push(MatchWithHitInfo {
+                    match_obj: Match {
+                        cost_info: MatchCostInfo {
+                            term_match_cost: 0,
+                            unmatched_position_cost: 0,
+                            inversion_cost: 0,
+                            static_cost: entry.cost,
+                        },
+                        match_type: MatchType::Traditional,
+                        entry_id,
+                        dictionary_id: 0,
+                    },
+                    matched_spans: vec![(start, start + query_ids.len())],
+                });
+            }
+
+            result.total += 1;
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{Builder, DictionaryEntry};
+    use crate::{EntrySource, StringVecSet};
+
+    fn characters(dict: &CompiledDictionary, result: &WordsContainingResult) -> Vec<String> {
+        result.matches.iter().map(|x| dict.get_display_entry(x.match_obj.entry_id).characters).collect()
+    }
+
+    #[test]
+    fn parse_pattern() {
+        assert_eq!(CharacterPosition::Start, WordsContainingQuery::parse_pattern("食*").unwrap().position);
+        assert_eq!(CharacterPosition::End, WordsContainingQuery::parse_pattern(" *食").unwrap().position);
+        let anywhere = WordsContainingQuery::parse_pattern("*食飯*").unwrap();
+        assert_eq!(CharacterPosition::Anywhere, anywhere.position);
+        assert_eq!("食飯", anywhere.characters);
+
+        assert!(WordsContainingQuery::parse_pattern("食").is_none());
+        assert!(WordsContainingQuery::parse_pattern("**").is_none());
+        assert!(WordsContainingQuery::parse_pattern("食*飯").is_none());
+        assert!(WordsContainingQuery::parse_pattern("sik* faan").is_none());
+    }
+
+    #[test]
+    fn words_containing() {
+        let mut builder = Builder::default();
+        for (traditional, jyutping, cost) in [
+            ("食", "sik6", 100),
+            ("食飯", "sik6 faan6", 200),
+            ("食嘢", "sik6 je5", 300),
+            ("零食", "ling4 sik6", 400),
+            ("飲食", "jam2 sik6", 500),
+            ("自助餐食物", "zi6 zo6 caan1 sik6 mat6", 600),
+            ("飯", "faan6", 700),
+        ] {
+            builder.entries.push(DictionaryEntry::new(traditional, jyutping, EntrySource::CCanto, StringVecSet::single("def".to_owned()), cost));
+        }
+
+        let dict = CompiledDictionary::from_builder(builder);
+
+        let starts = WordsContainingQuery::parse_pattern("食*").unwrap();
+        assert_eq!(vec!["食", "食飯", "食嘢"], characters(&dict, &dict.words_containing(&starts, 0, 10)));
+
+        let ends = WordsContainingQuery {
+            min_length: Some(2),
+            ..WordsContainingQuery::parse_pattern("*食").unwrap()
+        };
+        assert_eq!(vec!["零食", "飲食"], characters(&dict, &dict.words_containing(&ends, 0, 10)));
+
+        let anywhere = WordsContainingQuery::parse_pattern("*食*").unwrap();
+        let page = dict.words_containing(&anywhere, 2, 2);
+        assert_eq!(6, page.total);
+        assert_eq!(vec!["食嘢", "零食"], characters(&dict, &page));
+
+        let short = WordsContainingQuery { max_length: Some(2), ..anywhere.clone() };
+        assert_eq!(5, dict.words_containing(&short, 0, 10).total);
+
+        let long = dict.words_containing(&WordsContainingQuery { min_length: Some(3), ..anywhere }, 0, 10);
+        assert_eq!(vec![(3, 4)], long.matches[0].matched_spans);
+
+        assert_eq!(0, dict.words_containing(&WordsContainingQuery::parse_pattern("*獅*").unwrap(), 0, 10).total);
+    }
+}
@@ -1,11 +1,11 @@
 "use strict";
 
-import { JyutpingSearch } from "../pkg/index.js"
+// Parsing the dictionary and searching happen in a worker so they don't block typing
+const search_worker = new Worker(new URL("./search_worker.js", import.meta.url));
 
 const query_string = window.location.search;
 const url_params = new URLSearchParams(query_string);
 var query = url_params.get('q');
-var jyutping_search;
 var textfield = document.getElementById("entry");
 var resultsfield = document.getElementById("results");
 var explanation = document.getElementById("explanation");
@@ -14,33 +14,179 @@ var debug = url_params.get('debug') === '1';
 
 // Track current search state
 var currentQuery = "";
-var currentMaxResults = 12;
+const results_page_size = 12;
+
+// Only the newest search's results are shown, responses to older requests are dropped
+var latest_request_id = 0;
+var on_latest_result = null;
 
 const current_index_filename = "full.jyp_dict";
+// The most common entries, searchable while the tail downloads
+const current_core_filename = "full.jyp_core";
+const current_tail_filename = "full.jyp_tail";
+const current_examples_filename = "full.jyp_examples";
+
+function request_search(prefix, cursor, on_result) {
+    // Anything still queued in the worker is stale
+    search_worker.postMessage({ type: "Cancel", request_id: latest_request_id });
+
+    latest_request_id += 1;
+    on_latest_result = on_result;
+    search_worker.postMessage({
+        type: "Search",
+        request_id: latest_request_id,
+        query: prefix,
+        cursor: cursor,
+        max_results: results_page_size,
+    });
+}
 
-loadDictionary(current_index_filename)
+function cancel_searches() {
+    search_worker.postMessage({ type: "Cancel", request_id: latest_request_id });
+    latest_request_id += 1;
+    on_latest_result = null;
+}
+
+function search_first_page(prefix) {
+    request_search(prefix, undefined, search_result => {
+        resultsfield.innerHTML = "";
+        render(search_result);
+    });
+}
+
+search_worker.onmessage = e => {
+    const response = e.data;
+    switch (response.type) {
+        case "Loaded":
+            // Refetching a mismatched core and tail loads the dictionary again
+            if (!dictionary_loaded) {
+                dictionary_loaded = true;
+                on_dictionary_loaded();
+            }
+            break;
+        case "ChunkLoaded":
+            console.log("Loaded remaining entries");
+            on_all_entries_loaded();
+            break;
+        case "ChunkFailed":
+            console.error("Failed to append dictionary tail:", response.message);
+            if (!refetched_dictionary) {
+                refetched_dictionary = true;
+                refetch_split_dictionary();
+            }
+            else {
+                // Searches keep using the core
+                on_all_entries_loaded();
+            }
+            break;
+        case "ExamplesLoaded":
+            console.log("Loaded example sentences");
+            // Refresh the current results to include examples
+            if (currentQuery.length > 0) {
+                search_first_page(currentQuery);
+            }
+            break;
+        case "SearchResults":
+            if (response.request_id === latest_request_id && on_latest_result) {
+                on_latest_result(response.result);
+            }
+            break;
+        case "Error":
+            console.error("Search worker error:", response.message);
+            break;
+    }
+};
+
+// Falls back to the single file when the dictionary wasn't built split
+var has_tail = true;
+var dictionary_loaded = false;
+// The core and tail are only refetched once, after that the core is used alone
+var refetched_dictionary = false;
+
+loadDictionary(current_core_filename)
+  .catch(err => {
+    console.log("No core dictionary, loading the full dictionary", err);
+    has_tail = false;
+    return loadDictionary(current_index_filename);
+  })
   .then(data => {
     console.log("Got dictionary blob", data.byteLength, "bytes");
-    const data_array = new Uint8Array(data);
-    jyutping_search = new JyutpingSearch(data_array);
+    // Transfer rather than copy the buffer
+    search_worker.postMessage({ type: "LoadDictionary", data: data }, [data]);
+
+    if (has_tail) {
+        loadDictionary(current_tail_filename)
+            .then(tail => {
+                console.log("Got dictionary tail", tail.byteLength, "bytes");
+                search_worker.postMessage({ type: "AppendChunk", data: tail }, [tail]);
+            })
+            .catch(err => {
+                console.error("Failed to load dictionary tail:", err);
+                on_all_entries_loaded();
+            });
+    }
+
+    send_search_settings();
+});
+
+// A new dictionary in the worker starts without these
+function send_search_settings() {
+    // Optional ranking weight overrides, eg '{"english_base_penalty": 0}' to prefer english matches
+    const ranking_config = window.localStorage.getItem("ranking_config");
+    if (ranking_config) {
+        search_worker.postMessage({ type: "SetRankingConfig", config: ranking_config });
+    }
+
+    // Optional personal words searched alongside the main dictionary
+    // eg '[{"traditional": "老豆", "jyutping": "lou5 dau6", "definitions": ["dad"]}]'
+    const user_dictionary = window.localStorage.getItem("user_dictionary");
+    if (user_dictionary) {
+        search_worker.postMessage({ type: "SetUserDictionary", entries: user_dictionary });
+    }
+}
+
+// The cached core and tail can come from different builds, eg when only one was cached before the site updated.
+// Drop both from the caches and load them again from the server.
+async function refetch_split_dictionary() {
+    try {
+        const db = await openDB();
+        await evictCachedDict(db, current_core_filename);
+        await evictCachedDict(db, current_tail_filename);
+    } catch (err) {
+        console.warn("Failed to evict cached dictionary from IndexedDB:", err);
+    }
+
+    try {
+        const core = await loadDictionary(current_core_filename, 'reload');
+        const tail = await loadDictionary(current_tail_filename, 'reload');
+        console.log("Refetched dictionary core and tail", core.byteLength, tail.byteLength, "bytes");
+        search_worker.postMessage({ type: "LoadDictionary", data: core }, [core]);
+        send_search_settings();
+        search_worker.postMessage({ type: "AppendChunk", data: tail }, [tail]);
+    } catch (err) {
+        // The worker still has the core from before
+        console.error("Failed to refetch dictionary:", err);
+        on_all_entries_loaded();
+    }
+}
+
+function on_dictionary_loaded() {
     console.log("Finished search init!");
 
+    if (!has_tail) {
+        on_all_entries_loaded();
+    }
+
     textfield.removeAttribute("disabled");
     textfield.setAttribute("placeholder", "lou5 si1, teacher, 老師, ...");
     textfield.focus();
 
     const input_function = prefix => {
-        resultsfield.innerHTML = "";
-
         if (prefix.length > 0) {
-            // Reset max_results if query changed
-            if (currentQuery !== prefix) {
-                currentQuery = prefix;
-                currentMaxResults = 12;
-            }
-            
-            const results_string = jyutping_search.search(prefix, currentMaxResults);
-            render(results_string);
+            currentQuery = prefix;
+
+            // Previous results stay until the new ones arrive
+            search_first_page(prefix);
             explanation.hidden = true;
 
             // Update URL query parameter
@@ -49,10 +195,11 @@ loadDictionary(current_index_filename)
             window.history.replaceState({}, '', newUrl);
         }
         else {
+            cancel_searches();
+            resultsfield.innerHTML = "";
             textfield.setAttribute("placeholder", "");
             explanation.hidden = false;
             currentQuery = "";
-            currentMaxResults = 12;
 
             // Remove query parameter when search is empty
             const newUrl = new URL(window.location);
@@ -68,14 +215,17 @@ loadDictionary(current_index_filename)
         input_function(query);
         textfield.value = query;
     }
-});
+}
 
 // Get colouring classes for different translation sources
-function get_class_by_source(source) {
-    if (source === "CEDict") {
+function get_class_by_group(group) {
+    if (group.is_custom) {
+        return "custom-dict";
+    }
+    else if (group.source === "CEDict") {
         return "ce-dict";
     }
-    else if (source === "CCanto") {
+    else if (group.source === "CCanto") {
         return "cc-canto";
     }
     else {
@@ -83,9 +233,77 @@ function get_class_by_source(source) {
     }
 }
 
-// Render a search result
-function render(results_string) {
-    const search_result = JSON.parse(results_string)
+const cross_reference_text = {
+    VariantOf: "Variant of",
+    OldVariantOf: "Old variant of",
+    SeeAlso: "See also",
+    See: "See",
+    AbbreviationFor: "Abbreviation for",
+    SameAs: "Same as",
+    UsedIn: "Used in",
+};
+
+function make_usage_label(label) {
+    let label_elem = document.createElement("span");
+    label_elem.setAttribute("class", "usage-label");
+    label_elem.innerText = label;
+    return label_elem;
+}
+
+// A link that searches for the given characters, with their jyutping when known
+function make_linked_word(characters, jyutping) {
+    let link = document.createElement("a");
+    link.setAttribute("class", "linked-word");
+    link.href = `?q=${encodeURIComponent(characters)}`;
+    link.innerText = jyutping ? characters + " " + jyutping : characters;
+    return link;
+}
+
+function render_annotations(card, rendered_entry) {
+    const annotations = rendered_entry.annotations;
+
+    if (annotations.classifiers.length > 0) {
+        let classifiers_elem = document.createElement("p");
+        classifiers_elem.setAttribute("class", "item-english indent annotation");
+        classifiers_elem.appendChild(document.createTextNode("Classifiers: "));
+        annotations.classifiers.forEach((classifier, i) => {
+            if (i > 0) {
+                classifiers_elem.appendChild(document.createTextNode(", "));
+            }
+            classifiers_elem.appendChild(make_linked_word(classifier.characters, classifier.jyutping));
+        });
+        card.appendChild(classifiers_elem);
+    }
+
+    // Resolved references link to the target entry and show its jyutping
+    for (let reference of rendered_entry.cross_references) {
+        let reference_elem = document.createElement("p");
+        reference_elem.setAttribute("class", "item-english indent annotation");
+        reference_elem.appendChild(document.createTextNode(cross_reference_text[reference.kind] + " "));
+        reference_elem.appendChild(make_linked_word(reference.characters, reference.jyutping));
+        card.appendChild(reference_elem);
+    }
+
+    for (let comment of annotations.comments) {
+        let comment_elem = document.createElement("p");
+        comment_elem.setAttribute("class", "item-english entry-comment");
+        comment_elem.innerText = comment;
+        card.appendChild(comment_elem);
+    }
+}
+
+function render_examples(card, examples) {
+    for (let example of examples) {
+        let example_elem = document.createElement("p");
+        example_elem.setAttribute("class", "item-english indent example-sentence");
+        example_elem.appendChild(make_linked_word(example.chinese, example.jyutping));
+        example_elem.appendChild(document.createTextNode(" " + example.english));
+        card.appendChild(example_elem);
+    }
+}
+
+// Render a page of search results, later pages are appended below the first
+function render(search_result, is_first_page = true) {
     const results = search_result.results;
 
     if (results.length == 0) {
@@ -110,13 +328,23 @@ function render(results_string) {
         card.appendChild(hr_elem);
     }
 
+    if (is_first_page) {
+        let total_elem = document.createElement("p");
+        total_elem.setAttribute("class", "item-english result-total");
+        // Search stops early once the page is filled, so the total can be a lower bound
+        const total_text = search_result.total + (search_result.total_is_exact ? "" : "+");
+        total_elem.innerText = total_text + (search_result.total === 1 && search_result.total_is_exact ? " result" : " results");
+        // Ran out of time, there is no next page but a longer query will narrow it down
+        if (search_result.truncated) {
+            total_elem.innerText += " (search stopped early, try a longer query)";
+        }
+        card.appendChild(total_elem);
+    }
+
     for (let result of results) {
         let title = document.createElement("li");
         title.setAttribute("class", "card-item");
 
-        let source = result.rendered_entry.entry_source;
-        let source_class = get_class_by_source(source);
-
         let traditional_elem = document.createElement("span");
         traditional_elem.setAttribute("class", "item-english");
 
@@ -132,7 +360,7 @@ function render(results_string) {
         {
             let title_jyutping = document.createElement("h3");
             title_jyutping.setAttribute("class", "title");
-            title_jyutping.setAttribute("title", result.rendered_entry.entry_source);
+            title_jyutping.setAttribute("title", result.rendered_entry.definition_groups.map(x => x.source_name).join(", "));
 
             // Use pre-highlighted jyutping (already contains HTML markup)
             title_jyutping.innerHTML = makeJyutpingClickable(result.rendered_entry.jyutping);
@@ -142,36 +370,45 @@ function render(results_string) {
         title.appendChild(jyutping_elem);
         title.appendChild(traditional_elem);
 
+        if (result.rendered_entry.is_custom) {
+            title.classList.add("custom-entry");
+        }
+
         card.appendChild(title);
 
-        for (let i = 0; i < result.rendered_entry.english_definitions.length; i++) {
-            let english = result.rendered_entry.english_definitions[i];
-            let similar_elem = document.createElement("li");
-            similar_elem.setAttribute("class", "card-item");
+        // Merged entries keep the definitions from each source together
+        for (let group of result.rendered_entry.definition_groups) {
+            for (let i = group.start; i < group.end; i++) {
+                let english = result.rendered_entry.english_definitions[i];
+                let similar_elem = document.createElement("li");
+                similar_elem.setAttribute("class", "card-item");
 
-            let english_elem = document.createElement("span");
-            english_elem.setAttribute("class", "item-english indent");
+                let english_elem = document.createElement("span");
+                english_elem.setAttribute("class", "item-english indent");
 
-            // Use pre-highlighted english (already contains HTML markup)
-            english_elem.innerHTML = english;
+                for (let label of result.rendered_entry.annotations.labels) {
+                    if (label.definition === i) {
+                        english_elem.appendChild(make_usage_label(label.label));
+                    }
+                }
 
-            similar_elem.appendChild(english_elem);
+                // Use pre-highlighted english (already contains HTML markup)
+                english_elem.insertAdjacentHTML("beforeend", english);
 
-            card.appendChild(similar_elem);
-        }
+                similar_elem.appendChild(english_elem);
 
-        let source_elem = document.createElement("p");
-        source_elem.setAttribute("class", "item-english " + source_class);
-        if (source === "CEDict")
-        {
-            source_elem.innerText = "(Sourced from CEDict)";
-        }
-        else if (source == "CCanto")
-        {
-            source_elem.innerText = "(Sourced from CC-Canto)";
+                card.appendChild(similar_elem);
+            }
+
+            let source_elem = document.createElement("p");
+            source_elem.setAttribute("class", "item-english " + get_class_by_group(group));
+            source_elem.innerText = "(Sourced from " + group.source_name + ")";
+
+            card.appendChild(source_elem);
         }
 
-        card.appendChild(source_elem);
+        render_annotations(card, result.rendered_entry);
+        render_examples(card, result.examples);
 
         if (debug) {
             let debug_elem = document.createElement("div");
@@ -187,19 +424,14 @@ function render(results_string) {
 
     resultsfield.appendChild(card);
     
-    // Add "Load More" button only if we got the max number of results (meaning there might be more)
-    if (results.length === currentMaxResults) {
+    // Add "Load More" button only if there is another page, it fetches just that page
+    if (search_result.next_cursor !== undefined) {
         var loadMoreBtn = document.createElement("button");
         loadMoreBtn.setAttribute("class", "load-more-btn");
         loadMoreBtn.innerText = "More";
         loadMoreBtn.onclick = () => {
-            currentMaxResults *= 2;
-            const input_function_for_load_more = () => {
-                resultsfield.innerHTML = "";
-                const results_string = jyutping_search.search(currentQuery, currentMaxResults);
-                render(results_string);
-            };
-            input_function_for_load_more();
+            loadMoreBtn.remove();
+            request_search(currentQuery, search_result.next_cursor, next_page => render(next_page, false));
         };
         resultsfield.appendChild(loadMoreBtn);
     }
@@ -369,21 +601,51 @@ function wrapCharacters(sourceNode, targetNode) {
     }
 }
 
-async function loadDictionary(filename) {
+function on_all_entries_loaded() {
+    // Results from the core may be missing entries from the tail
+    if (has_tail && currentQuery.length > 0) {
+        search_first_page(currentQuery);
+    }
+
+    // Example sentences are a separate optional download, searches show them once loaded.
+    // They refer to every entry so wait for the tail.
+    loadExamples(current_examples_filename);
+}
+
+async function loadExamples(filename) {
     try {
-        const db = await openDB();
-        const cached = await getCachedDict(db, filename);
-        
-        if (cached) {
-            console.log("Using cached dictionary from IndexedDB");
-            return cached;
+        const response = await fetch(filename, { cache: 'force-cache' });
+        if (!response.ok) {
+            console.log("No example sentences available", response.status);
+            return;
         }
+
+        const data = await response.arrayBuffer();
+        console.log("Got example sentences", data.byteLength, "bytes");
+        search_worker.postMessage({ type: "LoadExamples", data: data }, [data]);
     } catch (err) {
-        console.warn("IndexedDB access failed, falling back to network:", err);
+        console.warn("Failed to load example sentences:", err);
+    }
+}
+
+// http_cache is the fetch cache mode, 'reload' skips both IndexedDB and the browser cache
+async function loadDictionary(filename, http_cache = 'force-cache') {
+    if (http_cache !== 'reload') {
+        try {
+            const db = await openDB();
+            const cached = await getCachedDict(db, filename);
+
+            if (cached) {
+                console.log("Using cached dictionary from IndexedDB");
+                return cached;
+            }
+        } catch (err) {
+            console.warn("IndexedDB access failed, falling back to network:", err);
+        }
     }
     
     console.log("Fetching dictionary from network");
-    const response = await fetch(filename, { cache: 'force-cache' });
+    const response = await fetch(filename, { cache: http_cache });
     if (!response.ok) {
         throw new Error(`Failed to fetch dictionary blob: ${response.status} ${response.statusText}`);
     }
@@ -437,6 +699,17 @@ function cacheDict(db, filename, data) {
         const store = transaction.objectStore(DB_STORE_NAME);
         const request = store.put(data, filename);
         
+        request.onerror = () => reject(request.error);
+        request.onsuccess = () => resolve();
+    });
+}
+
+function evictCachedDict(db, filename) {
+    return new Promise((resolve, reject) => {
+        const transaction = db.transaction([DB_STORE_NAME], 'readwrite');
+        const store = transaction.objectStore(DB_STORE_NAME);
+        const request = store.delete(filename);
+        
         request.onerror = () => reject(request.error);
         request.onsuccess = () => resolve();
     });
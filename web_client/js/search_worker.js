@@ -0,0 +1,70 @@
+"use strict";
+
+// Parses the dictionary and runs searches off the main thread.
+// Binary data is handled here, other requests are passed to JyutpingSearch.handle_message
+//     {type: "LoadDictionary", data: ArrayBuffer}  -> {type: "Loaded"}
+//     {type: "AppendChunk", data: ArrayBuffer}     -> {type: "ChunkLoaded"} or {type: "ChunkFailed", message}
+//     {type: "LoadExamples", data: ArrayBuffer}    -> {type: "ExamplesLoaded"} or {type: "Error", message}
+//     {type: "Search", request_id, query, cursor, max_results} -> {type: "SearchResults", request_id, result}
+//     {type: "Cancel", request_id}
+
+// Wasm has to be imported asynchronously, messages wait for it in order
+const wasm = import("../pkg/index.js");
+
+var jyutping_search;
+
+// Only the newest search waiting to run is kept, older ones are stale
+var pending_search = null;
+
+function run_pending_search() {
+    const message = pending_search;
+    pending_search = null;
+    self.postMessage(jyutping_search.handle_message(message));
+}
+
+function handle(JyutpingSearch, message) {
+    switch (message.type) {
+        case "LoadDictionary":
+            jyutping_search = new JyutpingSearch(new Uint8Array(message.data));
+            self.postMessage({ type: "Loaded" });
+            break;
+        case "AppendChunk":
+            // A mismatched tail leaves the core searchable
+            try {
+                jyutping_search.append_chunk(new Uint8Array(message.data));
+                self.postMessage({ type: "ChunkLoaded" });
+            }
+            catch (err) {
+                self.postMessage({ type: "ChunkFailed", message: err.toString() });
+            }
+            break;
+        case "LoadExamples":
+            try {
+                jyutping_search.load_examples(new Uint8Array(message.data));
+                self.postMessage({ type: "ExamplesLoaded" });
+            }
+            catch (err) {
+                self.postMessage({ type: "Error", message: err.toString() });
+            }
+            break;
+        case "Search":
+            // Give any queued messages a chance to replace or cancel this first
+            if (pending_search === null) {
+                setTimeout(run_pending_search, 0);
+            }
+            else {
+                self.postMessage({ type: "SearchCancelled", request_id: pending_search.request_id });
+            }
+            pending_search = message;
+            break;
+        default:
+            self.postMessage(jyutping_search.handle_message(message));
+            break;
+    }
+}
+
+self.onmessage = e => {
+    wasm
+        .then(({ JyutpingSearch }) => handle(JyutpingSearch, e.data))
+        .catch(err => self.postMessage({ type: "Error", message: err.toString() }));
+};
@@ -1,5 +1,6 @@
-use dictlib::{DebugLogger, Stopwatch, compiled_dictionary::CompiledDictionary, data_reader::DataReader, rendered_result::RenderedResult, search::{MatchWithHitInfo, Timings}};
+use dictlib::{DebugLogger, Stopwatch, character_info::CharacterQuery, compiled_dictionary::CompiledDictionary, data_reader::DataReader, dictionary_set::DictionarySet, examples::{ExampleSentence, ExampleStore}, rendered_result::RenderedResult, search::{MatchWithHitInfo, RankingConfig, SearchCursor, SearchOptions, Timings}, search_session::SearchSession, words_containing::WordsContainingQuery};
 use serde::Serialize;
+use tsify::Tsify;
 use wasm_bindgen::prelude::*;
 
 use crate::wasm_instant::WasmInstant;
@@ -11,6 +12,17 @@ macro_rules! log {
 }
 
 mod wasm_instant;
+mod worker;
+
+const MAX_DISPLAYED_EXAMPLES: usize = 3;
+
+// A slow search holds up the newer ones queued behind it
+const DEFAULT_TIME_BUDGET_MS: i32 = 50;
+
+// None becomes undefined, as in the generated TypeScript definitions
+fn to_js<T : Serialize + ?Sized>(value : &T) -> JsValue {
+    serde_wasm_bindgen::to_value(value).unwrap()
+}
 
 struct ConsoleLogger
 {
@@ -34,7 +46,15 @@ static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 #[wasm_bindgen]
 pub struct JyutpingSearch
 {
-    dict: CompiledDictionary,
+    // The main dictionary plus an optional user dictionary
+    dicts: DictionarySet,
+    ranking_config: RankingConfig,
+    // Reuses the previous keystroke's matches while typing
+    session: SearchSession,
+    // Searches taking longer return the best results found so far
+    time_budget_ms: Option<i32>,
+    // Worker searches with request ids up to this are dropped
+    cancelled_up_to: Option<u32>,
 }
 
 #[wasm_bindgen]
@@ -48,48 +68,204 @@ impl JyutpingSearch {
         let mut data_reader = DataReader::new(&compiled_data);
         let dict = CompiledDictionary::deserialize(&mut data_reader);
         Self {
-            dict,
+            dicts: DictionarySet::new(dict),
+            ranking_config: RankingConfig::default(),
+            session: SearchSession::new(),
+            time_budget_ms: Some(DEFAULT_TIME_BUDGET_MS),
+            cancelled_up_to: None,
+        }
+    }
+
+    /// Override ranking weights from a json object, unspecified weights keep their defaults.
+    pub fn set_ranking_config(&mut self, json : &str) -> Result<(), JsValue> {
+        let config = RankingConfig::from_json(json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        log!("Using ranking config {:?}", config);
+        self.ranking_config = config;
+        Ok(())
+    }
+
+    /// Milliseconds a search may take before returning partial results, None to always finish
+    pub fn set_time_budget(&mut self, time_budget_ms : Option<i32>) {
+        self.time_budget_ms = time_budget_ms;
+    }
+
+    /// Search a small user dictionary alongside the main one, from a json array of entries
+    ///     [{"traditional": "..", "jyutping": "..", "definitions": [".."], "cost": 100, "tags": [".."]}]
+    /// Replaces any previous user dictionary, an empty array removes it.
+    pub fn set_user_dictionary(&mut self, json : &str) -> Result<(), JsValue> {
+        self.dicts.set_user_dictionary_json(json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        self.session.reset();
+        log!("Using {} dictionaries", self.dicts.dictionaries.len());
+        Ok(())
+    }
+
+    /// Append the entries from a .jyp_tail file when the constructor was given the matching .jyp_core.
+    /// A tail from another build, eg a stale cached one, is rejected and the loaded entries are kept.
+    pub fn append_chunk(&mut self, chunk_data : Vec<u8>) -> Result<(), JsValue> {
+        log!("Received {} bytes of entries", chunk_data.len());
+        let mut data_reader = DataReader::new(&chunk_data);
+        self.dicts.main_mut().append_chunk(&mut data_reader).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        self.session.reset();
+        log!("Dictionary now has {} entries", self.dicts.main().entries.len());
+        Ok(())
+    }
+
+    /// Attach example sentences from a separately downloaded .jyp_examples file built alongside the dictionary.
+    /// Any chunks have to be appended first.
+    pub fn load_examples(&mut self, examples_data : Vec<u8>) -> Result<(), JsValue> {
+        log!("Received {} bytes of examples", examples_data.len());
+        let mut data_reader = DataReader::new(&examples_data);
+        let examples = ExampleStore::deserialize(&mut data_reader);
+        if !self.dicts.main().examples_fit(&examples) {
+            return Err(JsValue::from_str("Examples built for a different dictionary"));
+        }
+
+        self.dicts.main_mut().set_examples(examples);
+        Ok(())
+    }
+
+    /// Entries read the same as a jyutping string, ordered by cost
+    #[wasm_bindgen(unchecked_return_type = "DisplayDictionaryEntry[]")]
+    pub fn homophones(&self, jyutping : &str, tone_sensitive : bool, max_results : usize) -> JsValue {
+        let dict = self.dicts.main();
+        let entries : Vec<_> = dict.homophones(jyutping, tone_sensitive)
+            .into_iter()
+            .take(max_results)
+            .map(|x| dict.get_display_entry(x))
+            .collect();
+
+        to_js(&entries)
+    }
+
+    /// An entry by id without highlighting, eg the entry_id of a cross reference. Undefined if there is no such entry.
+    #[wasm_bindgen(unchecked_return_type = "RenderedResult | undefined")]
+    pub fn get_entry(&self, dictionary_id : usize, entry_id : usize) -> JsValue {
+        to_js(&self.dicts.render_entry(dictionary_id, entry_id))
+    }
+
+    /// Radical, stroke counts, readings and components of a single character, undefined if unknown
+    #[wasm_bindgen(unchecked_return_type = "CharacterInfo | undefined")]
+    pub fn character_info(&self, character : &str) -> JsValue {
+        let info = character.chars().next().and_then(|c| self.dicts.main().get_character_info(c));
+        to_js(&info)
+    }
+
+    /// Characters matching a radical / stroke count query, eg {radical: 141, additional_strokes: 2}
+    #[wasm_bindgen(unchecked_return_type = "string[]")]
+    pub fn find_characters(&self, #[wasm_bindgen(unchecked_param_type = "CharacterQuery")] query : JsValue) -> Result<JsValue, JsValue> {
+        let query : CharacterQuery = serde_wasm_bindgen::from_value(query)?;
+        let characters = self.dicts.main().find_characters(&query);
+        Ok(to_js(&characters))
+    }
+
+    /// Words containing characters, eg {characters: "食", position: "Start", max_length: 2}
+    #[wasm_bindgen(unchecked_return_type = "DisplaySearchResult")]
+    pub fn words_containing(&self, #[wasm_bindgen(unchecked_param_type = "WordsContainingQuery")] query : JsValue, offset : usize, max_results : usize) -> Result<JsValue, JsValue> {
+        let query : WordsContainingQuery = serde_wasm_bindgen::from_value(query)?;
+        Ok(to_js(&self.words_containing_query(&query, &query.characters, offset, max_results)))
+    }
+
+    /// Wildcard queries like 食* search words containing the characters, everything else is a normal search
+    #[wasm_bindgen(unchecked_return_type = "DisplaySearchResult")]
+    pub fn search(&mut self, prefix : &str, max_results: usize) -> JsValue {
+        self.search_page(prefix, None, max_results)
+    }
+
+    /// The page of results after next_cursor from a previous page of the same query, None for the first page
+    #[wasm_bindgen(unchecked_return_type = "DisplaySearchResult")]
+    pub fn search_page(&mut self, prefix : &str, cursor : Option<String>, max_results: usize) -> JsValue {
+        to_js(&self.search_display(prefix, cursor, max_results))
+    }
+
+    fn search_display(&mut self, prefix : &str, cursor : Option<String>, max_results: usize) -> DisplaySearchResult {
+        if let Some(query) = WordsContainingQuery::parse_pattern(prefix) {
+            // Words containing results are already in cost order so the cursor is just an offset
+            let offset = cursor.and_then(|x| x.parse().ok()).unwrap_or(0);
+            return self.words_containing_query(&query, prefix, offset, max_results);
+        }
+
+        let options = SearchOptions {
+            cursor: cursor.and_then(|x| SearchCursor::from_token(&x)),
+            time_budget_ms: self.time_budget_ms,
+            ..SearchOptions::new(max_results)
+        };
+
+        let stopwatch = WasmStopwatch::new();
+        let results = self.dicts.search_with_session(&mut self.session, prefix, &options, &self.ranking_config, &stopwatch);
+        if results.truncated {
+            log!("Search for '{}' ran out of time", prefix);
+        }
+
+        DisplaySearchResult {
+            results: self.display_results(results.matches, prefix),
+            timings: results.timings,
+            total: results.internal_candidates,
+            total_is_exact: results.exhaustive,
+            next_cursor: results.next_cursor.map(|x| x.to_token()),
+            truncated: results.truncated,
         }
     }
 
-    pub fn search(&self, prefix : &str, max_results: usize) -> String {
-        let stopwatch = Box::new(WasmStopwatch::new());
-        let results = self.dict.search(prefix, max_results, stopwatch);
+    fn words_containing_query(&self, query : &WordsContainingQuery, prefix : &str, offset : usize, max_results : usize) -> DisplaySearchResult {
+        let results = self.dicts.main().words_containing(query, offset, max_results);
+        let end = offset + results.matches.len();
+
+        DisplaySearchResult {
+            results: self.display_results(results.matches, prefix),
+            timings: Timings::default(),
+            total: results.total,
+            total_is_exact: true,
+            next_cursor: if end < results.total { Some(end.to_string()) } else { None },
+            truncated: false,
+        }
+    }
 
+    fn display_results(&self, matches : Vec<MatchWithHitInfo>, prefix : &str) -> Vec<DisplayResult> {
         let mut display_results = Vec::new();
-        for m in results.matches
+        for m in matches
         {
-            let rendered = RenderedResult::from_match(&m, &self.dict);
+            let rendered = self.dicts.render(&m);
+            let examples = self.dicts.get(m.match_obj.dictionary_id)
+                .examples_for(m.match_obj.entry_id, MAX_DISPLAYED_EXAMPLES)
+                .into_iter()
+                .cloned()
+                .collect();
+
             display_results.push(DisplayResult
             {
                 match_obj: m,
                 rendered_entry: rendered,
+                examples,
                 query: prefix.to_string(),
             })
         }
 
-        let dr = DisplaySearchResult {
-            results: display_results,
-            timings: results.timings,
-        };
-
-        serde_json::to_string(&dr).unwrap()
+        display_results
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Tsify)]
 struct DisplayResult
 {
     pub match_obj: MatchWithHitInfo,
     pub rendered_entry: RenderedResult,
+    // Empty until examples are loaded
+    pub examples: Vec<ExampleSentence>,
     pub query: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Tsify)]
 struct DisplaySearchResult
 {
     results: Vec<DisplayResult>,
     timings: Timings,
+    // Matches across all pages, a lower bound unless total_is_exact
+    total: usize,
+    total_is_exact: bool,
+    // Pass to search_page for the next page, undefined on the last page or when truncated
+    next_cursor: Option<String>,
+    // The search ran out of time, results are the best of the entries it reached
+    truncated: bool,
 }
 
 pub struct WasmStopwatch {
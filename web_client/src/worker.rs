@@ -0,0 +1,108 @@
+// Message protocol for running JyutpingSearch in a Web Worker, see js/search_worker.js.
+// The dictionary and examples are sent as ArrayBuffers and passed straight to the
+// constructor and load_examples, everything else is handled by handle_message.
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+use wasm_bindgen::prelude::*;
+
+use crate::{DisplaySearchResult, JyutpingSearch, to_js};
+
+/// Messages from the page, tagged by "type"
+///     {type: "Search", request_id: 3, query: "lou si", cursor: null, max_results: 12}
+#[derive(Debug, Deserialize, Tsify)]
+#[serde(tag = "type")]
+enum WorkerRequest {
+    SetRankingConfig {
+        config: String,
+    },
+    SetUserDictionary {
+        entries: String,
+    },
+    SetTimeBudget {
+        time_budget_ms: Option<i32>,
+    },
+    Search {
+        request_id: u32,
+        query: String,
+        // next_cursor from the previous page of the same query
+        cursor: Option<String>,
+        max_results: usize,
+    },
+    // Drops this and any earlier search that hasn't run yet
+    Cancel {
+        request_id: u32,
+    },
+}
+
+/// Replies to the page, one per request. A Search gets either SearchResults or SearchCancelled.
+#[derive(Serialize, Tsify)]
+#[serde(tag = "type")]
+enum WorkerResponse {
+    Updated,
+    SearchResults {
+        request_id: u32,
+        result: DisplaySearchResult,
+    },
+    SearchCancelled {
+        request_id: u32,
+    },
+    Cancelled {
+        request_id: u32,
+    },
+    Error {
+        message: String,
+    },
+}
+
+#[wasm_bindgen]
+impl JyutpingSearch {
+    /// Handle a request from the page, returning the response to post back
+    #[wasm_bindgen(unchecked_return_type = "WorkerResponse")]
+    pub fn handle_message(&mut self, #[wasm_bindgen(unchecked_param_type = "WorkerRequest")] message : JsValue) -> JsValue {
+        let response = match serde_wasm_bindgen::from_value::<WorkerRequest>(message) {
+            Ok(request) => self.handle_request(request),
+            Err(e) => WorkerResponse::Error {
+                message: format!("Invalid worker message: {}", e),
+            },
+        };
+
+        to_js(&response)
+    }
+}
+
+impl JyutpingSearch {
+    fn handle_request(&mut self, request : WorkerRequest) -> WorkerResponse {
+        match request {
+            WorkerRequest::SetRankingConfig { config } => Self::updated(self.set_ranking_config(&config)),
+            WorkerRequest::SetUserDictionary { entries } => Self::updated(self.set_user_dictionary(&entries)),
+            WorkerRequest::SetTimeBudget { time_budget_ms } => {
+                self.set_time_budget(time_budget_ms);
+                WorkerResponse::Updated
+            }
+            WorkerRequest::Search { request_id, query, cursor, max_results } => {
+                if self.cancelled_up_to.is_some_and(|x| request_id <= x) {
+                    return WorkerResponse::SearchCancelled { request_id };
+                }
+
+                WorkerResponse::SearchResults {
+                    request_id,
+                    result: self.search_display(&query, cursor, max_results),
+                }
+            }
+            WorkerRequest::Cancel { request_id } => {
+                self.cancelled_up_to = self.cancelled_up_to.max(Some(request_id));
+                WorkerResponse::Cancelled { request_id }
+            }
+        }
+    }
+
+    fn updated(result : Result<(), JsValue>) -> WorkerResponse {
+        match result {
+            Ok(()) => WorkerResponse::Updated,
+            Err(e) => WorkerResponse::Error {
+                message: e.as_string().unwrap_or_default(),
+            },
+        }
+    }
+}